@@ -0,0 +1,48 @@
+//! Module for replacing files atomically, so that they are never left partially written.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Replaces the file at the given path atomically with the given contents.
+///
+/// The contents are written to a temporary file next to it first, which is synced to disk before
+/// it is renamed, so that the file is never left partially written, not even after a crash.
+pub(super) fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with_mode(path, contents, None)
+}
+
+/// Replaces the file at the given path atomically with the given contents, like [`write`], and
+/// makes it only readable and writable by the owner.
+pub(super) fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with_mode(path, contents, Some(0o600))
+}
+
+/// Replaces the file at the given path atomically with the given contents and, on Unix, the given
+/// permissions (if any).
+fn write_with_mode(path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let mut file = options.open(&tmp_path)?;
+    // The mode only applies when the file is created, so restrict a leftover file as well.
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,132 @@
+//! Module for authenticating API requests using bearer tokens or HTTP Basic authentication.
+
+use rocket::http::{Header, Status as HttpStatus};
+use rocket::request::{self, FromRequest, Request};
+use rocket::{catch, Responder};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::Config;
+
+/// The configuration of the API authentication.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct AuthConfig {
+    /// The tokens that give access to the API
+    tokens: Vec<ApiToken>,
+}
+
+impl AuthConfig {
+    /// Returns the token that the given `Authorization` header value authenticates with (if any).
+    ///
+    /// Both bearer tokens and HTTP Basic authentication, with the token name as the username and
+    /// the token secret as the password, are supported.
+    fn authenticate(&self, authorization: &str) -> Option<&ApiToken> {
+        let (scheme, credentials) = authorization.split_once(' ')?;
+        if scheme.eq_ignore_ascii_case("Bearer") {
+            self.tokens.iter().find(|token| token.verify(credentials))
+        } else if scheme.eq_ignore_ascii_case("Basic") {
+            let credentials = base64::decode(credentials).ok()?;
+            let credentials = String::from_utf8(credentials).ok()?;
+            let (name, secret) = credentials.split_once(':')?;
+            self.tokens
+                .iter()
+                .find(|token| token.name == name && token.verify(secret))
+        } else {
+            None
+        }
+    }
+}
+
+/// A named token that gives access to the API.
+#[derive(Clone, Debug, Deserialize)]
+struct ApiToken {
+    /// The name of the token, also used as the username for HTTP Basic authentication
+    name: String,
+    /// The SHA-256 hash of the token secret (hexadecimal)
+    secret_sha256: String,
+}
+
+impl ApiToken {
+    /// Returns whether the given secret matches the hash of the token secret.
+    fn verify(&self, secret: &str) -> bool {
+        let secret_sha256 = Sha256::digest(secret.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        constant_time_eq(&secret_sha256, &self.secret_sha256.to_ascii_lowercase())
+    }
+}
+
+/// Compares two strings in time that only depends on their lengths.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a_byte, b_byte)| acc | (a_byte ^ b_byte))
+            == 0
+}
+
+/// Request guard that only succeeds if the request is authenticated, or if authentication is not
+/// configured.
+#[derive(Debug)]
+pub(super) struct Authenticated {
+    /// The name of the token the request is authenticated with (if authentication is configured)
+    token_name: Option<String>,
+}
+
+impl Authenticated {
+    /// Returns the name of the token the request is authenticated with.
+    ///
+    /// Returns `None` if authentication is not configured.
+    pub(super) fn token_name(&self) -> Option<&str> {
+        self.token_name.as_deref()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authenticated {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let auth_config = match req.rocket().state::<Config>() {
+            Some(Config {
+                auth: Some(auth_config),
+                ..
+            }) => auth_config,
+            _ => return request::Outcome::Success(Authenticated { token_name: None }),
+        };
+        let token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|authorization| auth_config.authenticate(authorization));
+        match token {
+            Some(token) => request::Outcome::Success(Authenticated {
+                token_name: Some(token.name.clone()),
+            }),
+            None => request::Outcome::Failure((HttpStatus::Unauthorized, ())),
+        }
+    }
+}
+
+/// A response for unauthenticated requests that challenges the client to authenticate.
+#[derive(Debug, Responder)]
+#[response(status = 401, content_type = "json")]
+pub(super) struct Unauthorized {
+    /// The error message
+    body: &'static str,
+    /// The authentication challenge
+    challenge: Header<'static>,
+}
+
+/// Catches unauthenticated requests and challenges the client to authenticate.
+#[catch(401)]
+pub(super) fn unauthorized() -> Unauthorized {
+    Unauthorized {
+        body: r#"{"error":"Unauthorized"}"#,
+        challenge: Header::new(
+            "WWW-Authenticate",
+            r#"Bearer, Basic realm="autarco-scraper""#,
+        ),
+    }
+}
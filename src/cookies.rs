@@ -0,0 +1,53 @@
+//! Module for persisting the cookie jars of the My Autarco sessions between runs.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use cookie_store::CookieStore;
+use reqwest_cookie_store::CookieStoreMutex;
+
+use super::atomic_file;
+
+/// Returns the path of the cookie jar file for the account with the given username in the given
+/// directory.
+///
+/// All characters of the username that are not alphanumeric are replaced to get a safe file name.
+pub(super) fn cookie_jar_path(dir: &Path, username: &str) -> PathBuf {
+    let file_stem = username
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    dir.join(format!("{}.cookies.json", file_stem))
+}
+
+/// Loads a cookie jar from the file at the given path.
+///
+/// Returns `None` if the file does not exist (yet).
+pub(super) fn load(path: &Path) -> io::Result<Option<CookieStore>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let cookie_store = CookieStore::load_json_all(BufReader::new(file))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(Some(cookie_store))
+}
+
+/// Saves the cookie jar to the file at the given path.
+///
+/// Session cookies are saved as well, because the My Autarco session depends on them. The file is
+/// only readable and writable by the owner and it is replaced atomically.
+pub(super) fn save(path: &Path, cookie_store: &CookieStoreMutex) -> io::Result<()> {
+    let mut contents = Vec::new();
+    cookie_store
+        .lock()
+        .expect("Cookie store mutex was poisoned")
+        .save_incl_expired_and_nonpersistent_json(&mut contents)
+        .map_err(io::Error::other)?;
+
+    atomic_file::write_private(path, &contents)
+}
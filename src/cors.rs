@@ -0,0 +1,113 @@
+//! Module for supporting Cross-Origin Resource Sharing (CORS) for browser-based clients.
+
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status as HttpStatus};
+use rocket::{Request, Response};
+use serde::Deserialize;
+
+use super::Config;
+
+/// The response headers that are exposed to browser-based clients.
+const EXPOSED_HEADERS: &str = "ETag, Last-Modified, Retry-After";
+
+/// The configuration of Cross-Origin Resource Sharing (CORS).
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct CorsConfig {
+    /// The origins that are allowed to access the API, or `*` to allow any origin
+    allowed_origins: Vec<String>,
+    /// The methods that are allowed to be used
+    #[serde(default = "default_allowed_methods")]
+    allowed_methods: Vec<String>,
+    /// The request headers that are allowed to be used
+    #[serde(default = "default_allowed_headers")]
+    allowed_headers: Vec<String>,
+    /// The time preflight responses can be cached for (s)
+    #[serde(default = "default_max_age_secs")]
+    max_age_secs: u64,
+}
+
+/// Returns the default methods that are allowed to be used.
+fn default_allowed_methods() -> Vec<String> {
+    vec![String::from("GET"), String::from("POST")]
+}
+
+/// Returns the default request headers that are allowed to be used.
+fn default_allowed_headers() -> Vec<String> {
+    vec![
+        String::from("Authorization"),
+        String::from("If-Modified-Since"),
+        String::from("If-None-Match"),
+    ]
+}
+
+/// Returns the default time preflight responses can be cached for.
+fn default_max_age_secs() -> u64 {
+    86400
+}
+
+impl CorsConfig {
+    /// Returns whether the given origin is allowed to access the API.
+    fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed_origin| allowed_origin == "*" || allowed_origin == origin)
+    }
+}
+
+/// Fairing that adds CORS headers to responses for allowed origins and answers preflight
+/// requests.
+///
+/// It does nothing if CORS is not configured.
+#[derive(Debug)]
+pub(super) struct Cors;
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let cors_config = match req.rocket().state::<Config>() {
+            Some(Config {
+                cors: Some(cors_config),
+                ..
+            }) => cors_config,
+            _ => return,
+        };
+        let origin = match req.headers().get_one("Origin") {
+            Some(origin) if cors_config.is_allowed_origin(origin) => origin,
+            _ => return,
+        };
+
+        res.set_raw_header("Access-Control-Allow-Origin", origin.to_owned());
+        res.adjoin_raw_header("Vary", "Origin");
+        res.set_raw_header("Access-Control-Expose-Headers", EXPOSED_HEADERS);
+
+        // Answer preflight requests, for which there are no routes.
+        let is_preflight = req.method() == Method::Options
+            && req.headers().contains("Access-Control-Request-Method");
+        if is_preflight {
+            res.set_status(HttpStatus::NoContent);
+            res.remove_header("Content-Type");
+            res.set_sized_body(0, Cursor::new(""));
+            res.set_raw_header(
+                "Access-Control-Allow-Methods",
+                cors_config.allowed_methods.join(", "),
+            );
+            res.set_raw_header(
+                "Access-Control-Allow-Headers",
+                cors_config.allowed_headers.join(", "),
+            );
+            res.set_raw_header(
+                "Access-Control-Max-Age",
+                cors_config.max_age_secs.to_string(),
+            );
+        }
+    }
+}
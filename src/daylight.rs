@@ -0,0 +1,255 @@
+//! Module for determining whether it is dark at the location of the solar panels.
+
+use chrono::NaiveDate;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use sunrise::{Coordinates, SolarDay, SolarEvent};
+use time::{Date, OffsetDateTime};
+use time_tz::{timezones, OffsetDateTimeExt, Tz};
+
+use super::{Status, StatusReason};
+
+/// The configuration of the daylight-aware polling schedule.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct DaylightConfig {
+    /// The latitude of the location of the solar panels (°)
+    latitude: f64,
+    /// The longitude of the location of the solar panels (°)
+    longitude: f64,
+    /// The (IANA) time zone of the location of the solar panels, e.g. `Europe/Amsterdam` (if any)
+    #[serde(default, deserialize_with = "deserialize_timezone")]
+    timezone: Option<&'static Tz>,
+    /// The time before sunrise and after sunset that is still considered to be light (s)
+    #[serde(default)]
+    margin_secs: u64,
+    /// The interval between data polls when it is dark (if polling at all)
+    pub(super) night_poll_interval: Option<u64>,
+}
+
+/// Deserializes an optional (IANA) time zone name into the time zone.
+fn deserialize_timezone<'de, D>(deserializer: D) -> Result<Option<&'static Tz>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| {
+            timezones::get_by_name(&name)
+                .ok_or_else(|| D::Error::custom(format!("unknown time zone: {}", name)))
+        })
+        .transpose()
+}
+
+impl DaylightConfig {
+    /// Returns the local date at the configured location at the given (UNIX) timestamp.
+    ///
+    /// This is the civil date in the configured time zone. Without a time zone, it is the local
+    /// solar date, which is approximated using the longitude.
+    pub(super) fn local_date(&self, timestamp: u64) -> Option<Date> {
+        match self.timezone {
+            Some(timezone) => OffsetDateTime::from_unix_timestamp(timestamp as i64)
+                .ok()
+                .map(|date_time| date_time.to_timezone(timezone).date()),
+            None => {
+                let solar_offset = (self.longitude / 15.0 * 3600.0) as i64;
+
+                OffsetDateTime::from_unix_timestamp(timestamp as i64 + solar_offset)
+                    .ok()
+                    .map(OffsetDateTime::date)
+            }
+        }
+    }
+
+    /// Returns whether it is dark at the configured location at the given (UNIX) timestamp.
+    ///
+    /// The sunrise and sunset are computed for the local date.
+    pub(super) fn is_dark(&self, timestamp: u64) -> bool {
+        let coordinates = match Coordinates::new(self.latitude, self.longitude) {
+            Some(coordinates) => coordinates,
+            None => return false,
+        };
+        let date = match self.local_date(timestamp).and_then(|date| {
+            NaiveDate::from_ymd_opt(
+                date.year(),
+                u8::from(date.month()).into(),
+                date.day().into(),
+            )
+        }) {
+            Some(date) => date,
+            None => return false,
+        };
+        let solar_day = SolarDay::new(coordinates, date);
+        let sunrise = solar_day.event_time(SolarEvent::Sunrise).timestamp();
+        let sunset = solar_day.event_time(SolarEvent::Sunset).timestamp();
+        let timestamp = timestamp as i64;
+        let margin = self.margin_secs as i64;
+
+        timestamp < sunrise - margin || timestamp > sunset + margin
+    }
+
+    /// Returns the status derived from the given last known status at the given (UNIX) timestamp
+    /// when it is dark.
+    ///
+    /// No power is produced, and the energy produced today and this month are reset after the
+    /// start of a new local day and month respectively.
+    pub(super) fn darkness_status(&self, last_status: &Status, timestamp: u64) -> Status {
+        let last_date = self.local_date(last_status.last_updated);
+        let date = self.local_date(timestamp);
+        let new_day = date != last_date;
+        let new_month = date.map(|date| (date.year(), date.month()))
+            != last_date.map(|date| (date.year(), date.month()));
+
+        Status {
+            current_w: 0,
+            today_kwh: last_status
+                .today_kwh
+                .map(|kwh| if new_day { 0 } else { kwh }),
+            month_kwh: last_status
+                .month_kwh
+                .map(|kwh| if new_month { 0 } else { kwh }),
+            last_updated: timestamp,
+            source: None,
+            reason: Some(StatusReason::Darkness),
+            stale: false,
+            ..*last_status
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2022-06-21 00:00:00 UTC
+    const MIDSUMMER: u64 = 1_655_769_600;
+
+    /// 2022-06-30 00:00:00 UTC
+    const END_OF_JUNE: u64 = 1_656_547_200;
+
+    /// Returns a configuration for Amsterdam with the given margin and time zone.
+    fn amsterdam(margin_secs: u64, timezone: Option<&'static Tz>) -> DaylightConfig {
+        DaylightConfig {
+            latitude: 52.37,
+            longitude: 4.89,
+            timezone,
+            margin_secs,
+            night_poll_interval: None,
+        }
+    }
+
+    /// Returns the sunrise in Amsterdam at midsummer as a (UNIX) timestamp.
+    fn midsummer_sunrise() -> u64 {
+        let coordinates = Coordinates::new(52.37, 4.89).unwrap();
+        let date = NaiveDate::from_ymd_opt(2022, 6, 21).unwrap();
+        let sunrise = SolarDay::new(coordinates, date).event_time(SolarEvent::Sunrise);
+
+        sunrise.timestamp() as u64
+    }
+
+    /// Returns a status with energy produced, updated at the given (UNIX) timestamp.
+    fn status(last_updated: u64) -> Status {
+        Status {
+            current_w: 23,
+            today_kwh: Some(4),
+            month_kwh: Some(112),
+            total_kwh: 6159,
+            last_updated,
+            source: Some(String::from("my_autarco")),
+            reason: None,
+            stale: true,
+        }
+    }
+
+    #[test]
+    fn is_dark_at_night() {
+        let daylight = amsterdam(0, None);
+
+        // Before sunrise (02:00 CEST), midday (14:00 CEST) and after sunset (23:30 CEST).
+        assert!(daylight.is_dark(MIDSUMMER));
+        assert!(!daylight.is_dark(MIDSUMMER + 12 * 3600));
+        assert!(daylight.is_dark(MIDSUMMER + 21 * 3600 + 1800));
+    }
+
+    #[test]
+    fn is_light_within_margin() {
+        let sunrise = midsummer_sunrise();
+
+        assert!(amsterdam(0, None).is_dark(sunrise - 600));
+        assert!(!amsterdam(1800, None).is_dark(sunrise - 600));
+        assert!(amsterdam(1800, None).is_dark(sunrise - 3600));
+    }
+
+    #[test]
+    fn is_never_dark_at_invalid_coordinates() {
+        let daylight = DaylightConfig {
+            latitude: 123.0,
+            ..amsterdam(0, None)
+        };
+
+        assert!(!daylight.is_dark(MIDSUMMER));
+    }
+
+    #[test]
+    fn uses_time_zone_for_local_date() {
+        let timezone = timezones::get_by_name("Europe/Amsterdam");
+        // 23:30 UTC is 01:30 CEST on the next day, but still before solar midnight.
+        let timestamp = MIDSUMMER - 1800;
+
+        let solar_date = amsterdam(0, None).local_date(timestamp).unwrap();
+        let civil_date = amsterdam(0, timezone).local_date(timestamp).unwrap();
+        assert_eq!(solar_date.day(), 20);
+        assert_eq!(civil_date.day(), 21);
+    }
+
+    #[test]
+    fn keeps_energy_within_day() {
+        let daylight = amsterdam(0, timezones::get_by_name("Europe/Amsterdam"));
+        // From 23:00 UTC (01:00 CEST) to 01:00 UTC (03:00 CEST) on the same local day.
+        let status = daylight.darkness_status(&status(MIDSUMMER - 3600), MIDSUMMER + 3600);
+
+        assert_eq!(status.current_w, 0);
+        assert_eq!(status.today_kwh, Some(4));
+        assert_eq!(status.month_kwh, Some(112));
+        assert_eq!(status.total_kwh, 6159);
+        assert_eq!(status.last_updated, MIDSUMMER + 3600);
+        assert_eq!(status.source, None);
+        assert_eq!(status.reason, Some(StatusReason::Darkness));
+        assert!(!status.stale);
+    }
+
+    #[test]
+    fn resets_energy_of_today_after_midnight() {
+        let daylight = amsterdam(0, timezones::get_by_name("Europe/Amsterdam"));
+        // From 21:00 UTC (23:00 CEST) to 23:00 UTC (01:00 CEST) on the next local day.
+        let status = daylight.darkness_status(&status(MIDSUMMER - 3 * 3600), MIDSUMMER - 3600);
+
+        assert_eq!(status.today_kwh, Some(0));
+        assert_eq!(status.month_kwh, Some(112));
+        assert_eq!(status.total_kwh, 6159);
+    }
+
+    #[test]
+    fn resets_energy_of_month_after_new_month() {
+        let daylight = amsterdam(0, timezones::get_by_name("Europe/Amsterdam"));
+        // From June 30 21:00 UTC (23:00 CEST) to 23:00 UTC (July 1 01:00 CEST).
+        let last_updated = END_OF_JUNE + 21 * 3600;
+        let status = daylight.darkness_status(&status(last_updated), last_updated + 2 * 3600);
+
+        assert_eq!(status.today_kwh, Some(0));
+        assert_eq!(status.month_kwh, Some(0));
+        assert_eq!(status.total_kwh, 6159);
+    }
+
+    #[test]
+    fn keeps_unknown_energy_unknown() {
+        let daylight = amsterdam(0, None);
+        let last_status = Status {
+            today_kwh: None,
+            month_kwh: None,
+            ..status(MIDSUMMER)
+        };
+        let status = daylight.darkness_status(&last_status, MIDSUMMER + 40 * 86_400);
+
+        assert_eq!(status.today_kwh, None);
+        assert_eq!(status.month_kwh, None);
+    }
+}
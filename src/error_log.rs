@@ -0,0 +1,63 @@
+//! Module for keeping a log of the most recent updater errors.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::unix_now;
+
+/// The maximum number of errors that are kept in the log.
+const ERROR_LOG_SIZE: usize = 100;
+
+/// The global log of the most recent updater errors.
+pub(super) static ERROR_LOG: Lazy<ErrorLog> = Lazy::new(ErrorLog::default);
+
+/// An updater error that occurred.
+#[derive(Clone, Debug, JsonSchema, Serialize)]
+pub(super) struct ErrorRecord {
+    /// The (UNIX) timestamp of when the error occurred
+    timestamp: u64,
+    /// The Autarco site ID the error occurred for (if specific to a site)
+    site_id: Option<String>,
+    /// The kind of error
+    kind: &'static str,
+    /// The description of the error
+    message: String,
+}
+
+/// The log of the most recent updater errors.
+#[derive(Debug, Default)]
+pub(super) struct ErrorLog {
+    /// The logged errors, oldest first
+    records: Mutex<VecDeque<ErrorRecord>>,
+}
+
+impl ErrorLog {
+    /// Logs an error of the given kind, optionally for the given site.
+    ///
+    /// If the log is full, the oldest error is discarded.
+    pub(super) fn push(&self, site_id: Option<&str>, kind: &'static str, message: String) {
+        let timestamp = unix_now();
+        let record = ErrorRecord {
+            timestamp,
+            site_id: site_id.map(String::from),
+            kind,
+            message,
+        };
+
+        let mut records = self.records.lock().expect("Error log mutex was poisoned");
+        if records.len() == ERROR_LOG_SIZE {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Returns the logged errors, newest first.
+    pub(super) fn records(&self) -> Vec<ErrorRecord> {
+        let records = self.records.lock().expect("Error log mutex was poisoned");
+        records.iter().rev().cloned().collect()
+    }
+}
@@ -0,0 +1,121 @@
+//! Module for responding with statuses including freshness metadata and caching headers.
+
+use std::io::Cursor;
+use std::time::{Duration, SystemTime};
+
+use rocket::http::{ContentType, Status as HttpStatus};
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::{unix_now, Config, Status};
+
+/// A status with freshness metadata, as served by the API.
+#[derive(Debug, JsonSchema, Serialize)]
+pub(super) struct FreshStatus {
+    /// The status itself
+    #[serde(flatten)]
+    status: Status,
+    /// The age of the status (s)
+    age_secs: u64,
+}
+
+/// A response containing a status with freshness metadata.
+///
+/// The response has `Cache-Control`, `Last-Modified` and `ETag` headers, and responds with
+/// 304 Not Modified if the status has not changed according to the `If-None-Match` or
+/// `If-Modified-Since` headers of the request.
+#[derive(Debug)]
+pub(super) struct StatusResponse {
+    /// The status with freshness metadata
+    fresh_status: FreshStatus,
+    /// The interval between data polls (s) currently in effect
+    poll_interval: u64,
+}
+
+impl StatusResponse {
+    /// Creates a response for the given status.
+    ///
+    /// The status is marked stale if it was restored and not updated since, or if it is older
+    /// than the configured number of poll intervals (at night, of the night poll interval).
+    pub(super) fn new(status: Status, config: &Config) -> Self {
+        let timestamp = unix_now();
+        let age_secs = timestamp.saturating_sub(status.last_updated);
+        let status = Status {
+            stale: status.stale || age_secs > config.stale_after_secs(&status, timestamp),
+            ..status
+        };
+
+        Self {
+            fresh_status: FreshStatus { status, age_secs },
+            poll_interval: config.poll_interval_at(timestamp),
+        }
+    }
+
+    /// Returns the time the status was last modified.
+    fn last_modified(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.fresh_status.status.last_updated)
+    }
+
+    /// Returns the entity tag of the status.
+    ///
+    /// It is a weak tag, because the age in the response changes while the status does not. It
+    /// changes when the status becomes stale, even though it was not updated.
+    fn etag(&self) -> String {
+        let status = &self.fresh_status.status;
+        let suffix = if status.stale { "-stale" } else { "" };
+
+        format!("W/\"{}{}\"", status.last_updated, suffix)
+    }
+
+    /// Returns whether the client already has the current status according to the conditional
+    /// headers of the given request.
+    ///
+    /// The `If-Modified-Since` header is ignored if an `If-None-Match` header is present.
+    fn is_not_modified(&self, req: &Request<'_>) -> bool {
+        let headers = req.headers();
+        match headers.get_one("If-None-Match") {
+            Some(if_none_match) => {
+                let etag = self.etag();
+                if_none_match.split(',').map(str::trim).any(|tag| {
+                    tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+                })
+            }
+            None => headers
+                .get_one("If-Modified-Since")
+                .and_then(|if_modified_since| httpdate::parse_http_date(if_modified_since).ok())
+                .is_some_and(|since| self.last_modified() <= since),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for StatusResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        // The status is expected to be updated again after a poll interval.
+        let max_age_secs = self
+            .poll_interval
+            .saturating_sub(self.fresh_status.age_secs);
+
+        let mut response = Response::build();
+        response
+            .raw_header("Cache-Control", format!("max-age={}", max_age_secs))
+            .raw_header(
+                "Last-Modified",
+                httpdate::fmt_http_date(self.last_modified()),
+            )
+            .raw_header("ETag", self.etag());
+
+        if self.is_not_modified(req) {
+            response.status(HttpStatus::NotModified);
+        } else {
+            let body = serde_json::to_string(&self.fresh_status)
+                .map_err(|_| HttpStatus::InternalServerError)?;
+            response
+                .header(ContentType::JSON)
+                .sized_body(body.len(), Cursor::new(body));
+        }
+
+        Ok(response.finalize())
+    }
+}
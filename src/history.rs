@@ -0,0 +1,103 @@
+//! Module for persisting the history of status samples in an embedded SQLite database.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, Error};
+
+use super::Status;
+
+/// The on-disk store of all successfully retrieved status samples.
+#[derive(Debug)]
+pub(super) struct History {
+    /// The connection to the SQLite database
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    /// Opens (or creates) the history database at the given path.
+    ///
+    /// The table to store the status samples in is created if it does not exist yet. The column
+    /// with the source of the samples is added to a table created before it existed.
+    pub(super) fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS status (
+                site_id TEXT NOT NULL,
+                last_updated INTEGER NOT NULL,
+                current_w INTEGER NOT NULL,
+                today_kwh INTEGER,
+                month_kwh INTEGER,
+                total_kwh INTEGER NOT NULL,
+                source TEXT,
+                PRIMARY KEY (site_id, last_updated)
+            )",
+            [],
+        )?;
+        let has_source = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('status') WHERE name = 'source'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )? > 0;
+        if !has_source {
+            conn.execute("ALTER TABLE status ADD COLUMN source TEXT", [])?;
+        }
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Stores a status sample of the given site.
+    ///
+    /// A sample with the same timestamp as an already stored sample of the site replaces it.
+    pub(super) fn insert(&self, site_id: &str, status: &Status) -> Result<(), Error> {
+        let conn = self.conn.lock().expect("History mutex was poisoned");
+        conn.execute(
+            "INSERT OR REPLACE INTO status
+                (site_id, last_updated, current_w, today_kwh, month_kwh, total_kwh, source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                site_id,
+                status.last_updated,
+                status.current_w,
+                status.today_kwh,
+                status.month_kwh,
+                status.total_kwh,
+                status.source
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the status samples of the given site with a timestamp in the given (inclusive)
+    /// range.
+    ///
+    /// The samples are ordered by their timestamp.
+    pub(super) fn range(&self, site_id: &str, from: u64, to: u64) -> Result<Vec<Status>, Error> {
+        let conn = self.conn.lock().expect("History mutex was poisoned");
+        let mut stmt = conn.prepare(
+            "SELECT last_updated, current_w, today_kwh, month_kwh, total_kwh, source
+             FROM status
+             WHERE site_id = ?1 AND last_updated BETWEEN ?2 AND ?3
+             ORDER BY last_updated",
+        )?;
+        let statuses = stmt
+            .query_map(params![site_id, from, to], |row| {
+                Ok(Status {
+                    last_updated: row.get(0)?,
+                    current_w: row.get(1)?,
+                    today_kwh: row.get(2)?,
+                    month_kwh: row.get(3)?,
+                    total_kwh: row.get(4)?,
+                    source: row.get(5)?,
+                    reason: None,
+                    stale: false,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(statuses)
+    }
+}
@@ -0,0 +1,137 @@
+//! Module for retrieving statuses from a generic JSON-over-HTTP API.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use autarco_scraper::{LoginError, UpdateError};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::field::Empty;
+use tracing::{debug, instrument, Span};
+
+use super::metrics::METRICS;
+use super::provider::{ProviderError, SolarProvider};
+use super::{Secret, Status};
+
+/// The configuration of a generic JSON-over-HTTP API provider.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct JsonProviderConfig {
+    /// The URL of the status of a site, in which `{site_id}` is replaced by the site ID
+    url: String,
+    /// The username to send using HTTP Basic authentication (if any)
+    username: Option<String>,
+    /// The password to send using HTTP Basic authentication (if any)
+    password: Option<Secret>,
+    /// Additional HTTP headers to send, e.g. with an API key
+    #[serde(default)]
+    headers: HashMap<String, Secret>,
+    /// The JSON pointer to the current power production (W)
+    current_w: String,
+    /// The JSON pointer to the total energy produced today (kWh), if provided
+    today_kwh: Option<String>,
+    /// The JSON pointer to the total energy produced this month (kWh), if provided
+    month_kwh: Option<String>,
+    /// The JSON pointer to the total energy produced since installation (kWh)
+    total_kwh: String,
+}
+
+/// A provider that retrieves statuses from a generic JSON-over-HTTP API.
+///
+/// The credentials of the provider (if configured) are sent using HTTP Basic authentication; the
+/// credentials of the account are never sent.
+#[derive(Debug)]
+pub(super) struct JsonProvider {
+    /// The configuration of the provider
+    config: JsonProviderConfig,
+    /// The HTTP client
+    client: Client,
+}
+
+impl JsonProvider {
+    /// Creates a provider with the given configuration.
+    pub(super) fn new(config: &JsonProviderConfig) -> Result<Self, reqwest::Error> {
+        let client = Client::builder().build()?;
+
+        Ok(Self {
+            config: config.clone(),
+            client,
+        })
+    }
+}
+
+/// Returns the number at the given JSON pointer in the given value, rounded.
+fn number_at(value: &Value, pointer: &str) -> Result<u32, ProviderError> {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_f64)
+        .map(|number| number.round() as u32)
+        .ok_or_else(|| ProviderError::MissingValue(pointer.to_owned()))
+}
+
+#[rocket::async_trait]
+impl SolarProvider for JsonProvider {
+    /// Does nothing, because no session is needed.
+    async fn login(&self) -> Result<(), LoginError> {
+        Ok(())
+    }
+
+    /// Retrieves the status of the site and looks up the values using the configured JSON
+    /// pointers.
+    #[instrument(
+        skip_all,
+        fields(site_id = %site_id, endpoint = "json", http_status = Empty, latency_ms = Empty)
+    )]
+    async fn fetch_status(
+        &self,
+        site_id: &str,
+        last_updated: u64,
+    ) -> Result<Status, ProviderError> {
+        let url = self.config.url.replace("{site_id}", site_id);
+        let mut request = self.client.get(url);
+        if let Some(username) = &self.config.username {
+            let password = self.config.password.as_ref().map(Secret::expose);
+            request = request.basic_auth(username, password);
+        }
+        for (name, value) in &self.config.headers {
+            request = request.header(name.as_str(), value.expose());
+        }
+
+        let start = Instant::now();
+        let result = request.send().await;
+        let latency = start.elapsed();
+        Span::current().record("latency_ms", latency.as_millis() as u64);
+        METRICS.observe_latency("json", latency.as_secs_f64());
+        let response = result.map_err(UpdateError::from)?;
+        Span::current().record("http_status", response.status().as_u16());
+        debug!("Fetched status data");
+
+        match response.status() {
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                return Err(UpdateError::Auth(status).into())
+            }
+            StatusCode::SERVICE_UNAVAILABLE => return Err(UpdateError::Maintenance.into()),
+            status if !status.is_success() => return Err(UpdateError::HttpStatus(status).into()),
+            _ => {}
+        }
+        let body = response.text().await.map_err(UpdateError::from)?;
+        let value: Value = serde_json::from_str(&body).map_err(UpdateError::from)?;
+        let optional_number_at = |pointer: &Option<String>| {
+            pointer
+                .as_deref()
+                .map(|pointer| number_at(&value, pointer))
+                .transpose()
+        };
+
+        Ok(Status {
+            current_w: number_at(&value, &self.config.current_w)?,
+            today_kwh: optional_number_at(&self.config.today_kwh)?,
+            month_kwh: optional_number_at(&self.config.month_kwh)?,
+            total_kwh: number_at(&value, &self.config.total_kwh)?,
+            last_updated,
+            source: None,
+            reason: None,
+            stale: false,
+        })
+    }
+}
@@ -0,0 +1,420 @@
+//! Library to retrieve the statistical data of solar panels from the My Autarco site.
+//!
+//! The [`AutarcoClient`] logs in on the My Autarco site, renews the session when it has expired
+//! and retrieves the typed KPI data of sites using the API of the site.
+//!
+//! ```no_run
+//! use autarco_scraper::{AutarcoClient, DEFAULT_BASE_URL};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let client = AutarcoClient::new(DEFAULT_BASE_URL, "foo@domain.tld", "secret")?;
+//! client.login().await?;
+//! let power = client.power("abc123de").await?;
+//! println!("Currently producing {} W", power.pv_now);
+//! # Ok(())
+//! # }
+//! ```
+#![warn(
+    clippy::all,
+    missing_debug_implementations,
+    rust_2018_idioms,
+    rustdoc::broken_intra_doc_links
+)]
+#![deny(missing_docs)]
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{LOCATION, SET_COOKIE};
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, StatusCode};
+use reqwest_cookie_store::CookieStoreMutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{debug, instrument, warn, Span};
+use url::{ParseError, Url};
+
+/// The default base URL of My Autarco site.
+pub const DEFAULT_BASE_URL: &str = "https://my.autarco.com";
+
+/// Error that can occur when creating an [`AutarcoClient`].
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The base URL is not a valid URL
+    #[error("invalid base URL: {0}")]
+    InvalidBaseUrl(#[from] ParseError),
+    /// The base URL is not an HTTP(S) URL
+    #[error("base URL is not an HTTP(S) URL: {0}")]
+    UnsupportedBaseUrl(Url),
+    /// The HTTP client could not be created
+    #[error("failed to create HTTP client: {0}")]
+    Build(#[from] reqwest::Error),
+}
+
+/// Error that can occur when logging in on the My Autarco site.
+#[derive(Debug, thiserror::Error)]
+pub enum LoginError {
+    /// The login request could not be performed
+    #[error("login request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    /// The credentials were rejected
+    #[error("invalid credentials")]
+    InvalidCredentials,
+    /// The login page was returned again instead of logging in
+    #[error("redirected back to the login page (credentials are probably invalid)")]
+    UnexpectedLoginPage,
+    /// The login response has an unexpected HTTP status
+    #[error("unexpected HTTP status: {0}")]
+    HttpStatus(StatusCode),
+    /// No session cookie was acquired
+    #[error("no session cookie was set")]
+    MissingSessionCookie,
+}
+
+impl LoginError {
+    /// Returns the kind of the login error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoginError::Request(_) => "request",
+            LoginError::InvalidCredentials => "invalid_credentials",
+            LoginError::UnexpectedLoginPage => "unexpected_login_page",
+            LoginError::HttpStatus(_) => "http_status",
+            LoginError::MissingSessionCookie => "missing_session_cookie",
+        }
+    }
+
+    /// Returns whether the login was rejected by the My Autarco site.
+    ///
+    /// This is not the case if the login failed because of, for example, network problems.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            LoginError::InvalidCredentials
+                | LoginError::UnexpectedLoginPage
+                | LoginError::MissingSessionCookie
+        )
+    }
+}
+
+/// Error that can occur when retrieving KPI data from the My Autarco site.
+#[derive(Debug, thiserror::Error)]
+pub enum UpdateError {
+    /// The request could not be performed, e.g. because of a connection error or a timeout
+    #[error("request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The request was not authorized, i.e. the session has expired
+    #[error("unauthorized (HTTP status {0})")]
+    Auth(StatusCode),
+    /// The My Autarco site is unavailable because of maintenance
+    #[error("upstream is unavailable, probably because of maintenance")]
+    Maintenance,
+    /// The response has an unexpected HTTP status
+    #[error("unexpected HTTP status: {0}")]
+    HttpStatus(StatusCode),
+    /// The response could not be decoded
+    #[error("failed to decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+    /// The API URL for the site ID and endpoint could not be built
+    #[error("invalid API URL: {0}")]
+    InvalidUrl(#[from] ParseError),
+}
+
+impl UpdateError {
+    /// Returns the kind of the update error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UpdateError::Transport(_) => "transport",
+            UpdateError::Auth(_) => "auth",
+            UpdateError::Maintenance => "maintenance",
+            UpdateError::HttpStatus(_) => "http_status",
+            UpdateError::Decode(_) => "decode",
+            UpdateError::InvalidUrl(_) => "invalid_url",
+        }
+    }
+}
+
+/// Error that can occur when retrieving KPI data, including renewing the session.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Logging in again to renew the session failed
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    /// Retrieving the KPI data failed
+    #[error(transparent)]
+    Update(#[from] UpdateError),
+}
+
+/// The energy data returned by the energy API endpoint.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Energy {
+    /// Total energy produced today (kWh), if provided
+    #[serde(default)]
+    pub pv_today: Option<u32>,
+    /// Total energy produced this month (kWh), if provided
+    #[serde(default)]
+    pub pv_month: Option<u32>,
+    /// Total energy produced since installation (kWh)
+    pub pv_to_date: u32,
+}
+
+/// The power data returned by the power API endpoint.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Power {
+    /// Current power production (W)
+    pub pv_now: u32,
+}
+
+/// Observer of the activity of an [`AutarcoClient`], e.g. to collect metrics.
+///
+/// All methods have an empty default implementation.
+pub trait Observer: Send + Sync {
+    /// Called after a request to the given endpoint completed (successfully or not).
+    fn request_completed(&self, _endpoint: &str, _latency: Duration) {}
+
+    /// Called after logging in, including logins to renew the session, with the result.
+    fn logged_in(&self, _result: &Result<(), LoginError>) {}
+}
+
+/// Client for the My Autarco site of an account.
+///
+/// The session cookie is kept in the cookie jar of the client. Cloning the client is cheap and
+/// the clone shares the session.
+#[derive(Clone)]
+pub struct AutarcoClient {
+    /// The base URL of the My Autarco site, ending with a slash
+    base_url: Url,
+    /// The login URL of the My Autarco site
+    login_url: Url,
+    /// The username of the account to login with
+    username: String,
+    /// The password of the account to login with
+    password: String,
+    /// The HTTP client that keeps the session cookie
+    client: Client,
+    /// The HTTP client to login with, which shares the cookie jar but does not follow redirects
+    login_client: Client,
+    /// The cookie jar of the HTTP client
+    cookie_jar: Arc<CookieStoreMutex>,
+    /// The observer of the client activity (if any)
+    observer: Option<Arc<dyn Observer>>,
+}
+
+impl fmt::Debug for AutarcoClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutarcoClient")
+            .field("base_url", &self.base_url.as_str())
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .field("observed", &self.observer.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AutarcoClient {
+    /// Creates a client for the My Autarco site at the given base URL and the given account.
+    ///
+    /// The client is not logged in yet, see [`AutarcoClient::login`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the base URL is not a valid HTTP(S) URL or if the HTTP client cannot
+    /// be created.
+    pub fn new(base_url: &str, username: &str, password: &str) -> Result<Self, ClientError> {
+        Self::with_cookie_jar(base_url, username, password, Arc::default())
+    }
+
+    /// Creates a client like [`AutarcoClient::new`] that keeps its cookies in the given jar.
+    ///
+    /// This can be used to persist the session and restore it later.
+    pub fn with_cookie_jar(
+        base_url: &str,
+        username: &str,
+        password: &str,
+        cookie_jar: Arc<CookieStoreMutex>,
+    ) -> Result<Self, ClientError> {
+        let mut base_url = Url::parse(base_url)?;
+        if !matches!(base_url.scheme(), "http" | "https") {
+            return Err(ClientError::UnsupportedBaseUrl(base_url));
+        }
+        // Ensure the path ends with a slash, so that joining keeps the last path segment.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        let login_url = base_url.join("auth/login")?;
+        let client = ClientBuilder::new()
+            .cookie_provider(Arc::clone(&cookie_jar))
+            .build()?;
+        let login_client = ClientBuilder::new()
+            .cookie_provider(Arc::clone(&cookie_jar))
+            .redirect(Policy::none())
+            .build()?;
+
+        Ok(Self {
+            base_url,
+            login_url,
+            username: username.to_owned(),
+            password: password.to_owned(),
+            client,
+            login_client,
+            cookie_jar,
+            observer: None,
+        })
+    }
+
+    /// Sets the observer of the client activity.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+
+        self
+    }
+
+    /// Returns the cookie jar that keeps the session cookie.
+    pub fn cookie_jar(&self) -> &Arc<CookieStoreMutex> {
+        &self.cookie_jar
+    }
+
+    /// Returns the URL of an API endpoint for the given site ID and endpoint.
+    fn api_url(&self, site_id: &str, endpoint: &str) -> Result<Url, ParseError> {
+        self.base_url
+            .join(&format!("api/site/{}/kpis/{}", site_id, endpoint))
+    }
+
+    /// Logs in on the My Autarco site.
+    ///
+    /// It mainly stores the acquired cookie in the client's cookie jar.
+    ///
+    /// The login is checked for having succeeded: the response should not have an HTTP error
+    /// status, should not be a redirect back to the login page and it should set a session
+    /// cookie. Redirects are not followed, so that the response of the login itself is checked.
+    pub async fn login(&self) -> Result<(), LoginError> {
+        let result = self.perform_login().await;
+        if let Some(observer) = &self.observer {
+            observer.logged_in(&result);
+        }
+
+        result
+    }
+
+    /// Performs the login request and checks whether it succeeded.
+    #[instrument(skip_all, fields(endpoint = "login", http_status = Empty, latency_ms = Empty))]
+    async fn perform_login(&self) -> Result<(), LoginError> {
+        debug!("Logging in...");
+        let params = [
+            ("username", self.username.as_str()),
+            ("password", self.password.as_str()),
+        ];
+        let login_url = &self.login_url;
+
+        let start = Instant::now();
+        let result = self
+            .login_client
+            .post(login_url.clone())
+            .form(&params)
+            .send()
+            .await;
+        self.observe_request("login", start.elapsed());
+        let response = result?;
+        Span::current().record("http_status", response.status().as_u16());
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(LoginError::InvalidCredentials)
+            }
+            status if status.is_redirection() => {
+                let location = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| login_url.join(location).ok());
+                match location {
+                    Some(location) if location.path() == login_url.path() => {
+                        return Err(LoginError::UnexpectedLoginPage)
+                    }
+                    Some(_) => {}
+                    None => return Err(LoginError::HttpStatus(status)),
+                }
+            }
+            status if !status.is_success() => return Err(LoginError::HttpStatus(status)),
+            _ => {}
+        }
+        // Check the response itself, the cookie jar may still contain an old session cookie.
+        if !response.headers().contains_key(SET_COOKIE) {
+            return Err(LoginError::MissingSessionCookie);
+        }
+
+        Ok(())
+    }
+
+    /// Records the latency of a completed request to the given endpoint.
+    fn observe_request(&self, endpoint: &str, latency: Duration) {
+        Span::current().record("latency_ms", latency.as_millis() as u64);
+        if let Some(observer) = &self.observer {
+            observer.request_completed(endpoint, latency);
+        }
+    }
+
+    /// Retrieves and decodes the data of the given KPI endpoint for the given site.
+    #[instrument(
+        skip_all,
+        fields(site_id = %site_id, endpoint = %endpoint, http_status = Empty, latency_ms = Empty)
+    )]
+    async fn fetch_kpi<T: DeserializeOwned>(
+        &self,
+        site_id: &str,
+        endpoint: &str,
+    ) -> Result<T, UpdateError> {
+        let url = self.api_url(site_id, endpoint)?;
+        let start = Instant::now();
+        let result = self.client.get(url).send().await;
+        self.observe_request(endpoint, start.elapsed());
+        let response = result?;
+        Span::current().record("http_status", response.status().as_u16());
+        debug!("Fetched KPI data");
+
+        match response.status() {
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                return Err(UpdateError::Auth(status))
+            }
+            StatusCode::SERVICE_UNAVAILABLE => return Err(UpdateError::Maintenance),
+            status if !status.is_success() => return Err(UpdateError::HttpStatus(status)),
+            _ => {}
+        }
+        let body = response.text().await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Retrieves the data of the given KPI endpoint for the given site, renewing the session if
+    /// it has expired.
+    async fn fetch_kpi_renewing<T: DeserializeOwned>(
+        &self,
+        site_id: &str,
+        endpoint: &str,
+    ) -> Result<T, Error> {
+        match self.fetch_kpi(site_id, endpoint).await {
+            Err(UpdateError::Auth(_)) => {
+                warn!("Session has expired, trying to log in again");
+                self.login().await?;
+
+                Ok(self.fetch_kpi(site_id, endpoint).await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Retrieves the energy data of the given site.
+    ///
+    /// If the session has expired, it logs in again and retries once.
+    pub async fn energy(&self, site_id: &str) -> Result<Energy, Error> {
+        self.fetch_kpi_renewing(site_id, "energy").await
+    }
+
+    /// Retrieves the power data of the given site.
+    ///
+    /// If the session has expired, it logs in again and retries once.
+    pub async fn power(&self, site_id: &str) -> Result<Power, Error> {
+        self.fetch_kpi_renewing(site_id, "power").await
+    }
+}
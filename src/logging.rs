@@ -0,0 +1,31 @@
+//! Module for setting up the logging of the updater.
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+/// The format of the log output.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum LogFormat {
+    /// Human-readable output
+    #[default]
+    Human,
+    /// JSON output, one object per line
+    Json,
+}
+
+/// Sets up the global logging subscriber using the given format.
+///
+/// The verbosity can be filtered using the `RUST_LOG` environment variable and defaults to the
+/// `info` level. Note that Rocket itself keeps using its own logger.
+pub(super) fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match format {
+        LogFormat::Human => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to set up logging: {}", e);
+    }
+}
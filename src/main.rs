@@ -7,69 +7,727 @@
 )]
 #![deny(missing_docs)]
 
-use std::sync::Mutex;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use once_cell::sync::Lazy;
+use autarco_scraper::DEFAULT_BASE_URL;
+use once_cell::sync::OnceCell;
 use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status as HttpStatus};
+use rocket::response::content::RawHtml;
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::Debug;
 use rocket::serde::json::Json;
-use rocket::{get, routes};
+use rocket::tokio::select;
+use rocket::tokio::sync::watch;
+use rocket::{catchers, get, post, routes, Shutdown, State};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-use self::update::update_loop;
+use self::auth::{AuthConfig, Authenticated};
+use self::cors::{Cors, CorsConfig};
+use self::daylight::DaylightConfig;
+use self::error_log::{ErrorRecord, ERROR_LOG};
+use self::freshness::StatusResponse;
+use self::history::History;
+use self::metrics::METRICS;
+use self::mqtt::{MqttConfig, MqttPublisher};
+use self::openapi::{OpenApiDocument, API_V1_BASE};
+use self::provider::{CrossCheckConfig, ProviderConfig, ProviderKind};
+use self::pvoutput::{PvOutputConfig, PvOutputUploader};
+use self::refresh::{RefreshAuthorized, RefreshError};
+use self::snapshot::Snapshot;
+use self::supervisor::{supervise, BackoffConfig};
+use self::update::UpdaterState;
 
+mod atomic_file;
+mod auth;
+mod cookies;
+mod cors;
+mod daylight;
+mod error_log;
+mod freshness;
+mod history;
+mod json_provider;
+mod logging;
+mod metrics;
+mod modbus_provider;
+mod mqtt;
+mod openapi;
+mod provider;
+mod pvoutput;
+mod refresh;
+mod snapshot;
+mod supervisor;
 mod update;
 
-/// The base URL of My Autarco site.
-const BASE_URL: &str = "https://my.autarco.com";
+/// The extra configuration necessary to access the My Autarco site.
+#[derive(Clone, Debug, Deserialize)]
+struct Config {
+    /// The base URL of the My Autarco site
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// The provider to retrieve the statuses from
+    #[serde(default)]
+    provider: ProviderConfig,
+    /// The providers to fall back to, in order, if retrieving a status fails
+    #[serde(default)]
+    fallback_providers: Vec<ProviderConfig>,
+    /// The cross-checking of statuses with the next providers (if enabled)
+    cross_check: Option<CrossCheckConfig>,
+    /// The username of the single account to login with (if any)
+    username: Option<String>,
+    /// The password of the single account to login with (if any)
+    password: Option<Secret>,
+    /// The Autarco site ID to track of the single account (if any)
+    site_id: Option<String>,
+    /// The (additional) accounts to login with and the Autarco sites to track
+    #[serde(default)]
+    accounts: Vec<AccountConfig>,
+    /// The path of the database to store the status history in (if enabled)
+    history_path: Option<PathBuf>,
+    /// The path of the file to persist the last known statuses in (if enabled)
+    state_path: Option<PathBuf>,
+    /// The path of the directory to persist the session cookie jars in (if enabled)
+    cookie_jar_dir: Option<PathBuf>,
+    /// The interval between data polls (s)
+    #[serde(default = "default_poll_interval")]
+    poll_interval: u64,
+    /// The interval with which the update loop wakes up to check if an update is due (s)
+    #[serde(default = "default_wake_interval")]
+    wake_interval: u64,
+    /// The daylight-aware polling schedule (if enabled)
+    daylight: Option<DaylightConfig>,
+    /// The authentication of the API (if enabled)
+    auth: Option<AuthConfig>,
+    /// The Cross-Origin Resource Sharing (CORS) configuration (if enabled)
+    cors: Option<CorsConfig>,
+    /// The minimum interval between polls when refreshing on demand (s)
+    #[serde(default = "default_refresh_min_interval")]
+    refresh_min_interval: u64,
+    /// The number of poll intervals after which a status is considered stale
+    #[serde(default = "default_stale_after_polls")]
+    stale_after_polls: u64,
+    /// The MQTT broker to publish the status to (if enabled)
+    mqtt: Option<MqttConfig>,
+    /// The PVOutput.org system to upload the statuses to (if enabled)
+    pvoutput: Option<PvOutputConfig>,
+    /// The backoff on failures
+    #[serde(default)]
+    backoff: BackoffConfig,
+}
 
-/// The interval between data polls.
+/// A secret configuration value, such as a password.
+///
+/// It is redacted when formatted for debugging so that it never ends up in the logs.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+struct Secret(String);
+
+impl Secret {
+    /// Returns the actual secret value.
+    fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Returns the default base URL of the My Autarco site.
+fn default_base_url() -> String {
+    String::from(DEFAULT_BASE_URL)
+}
+
+/// Returns the default interval between data polls.
 ///
 /// This depends on with which interval Autaurco processes new information from the invertor.
-const POLL_INTERVAL: u64 = 300;
+fn default_poll_interval() -> u64 {
+    300
+}
 
-/// The extra configuration necessary to access the My Autarco site.
-#[derive(Debug, Deserialize)]
-struct Config {
-    /// The username of the account to login with
-    username: String,
-    /// The password of the account to login with
-    password: String,
-    /// The Autarco site ID to track
-    site_id: String,
+/// Returns the default interval with which the update loop wakes up.
+fn default_wake_interval() -> u64 {
+    10
+}
+
+/// Returns the default minimum interval between polls when refreshing on demand.
+fn default_refresh_min_interval() -> u64 {
+    60
+}
+
+/// Returns the default number of poll intervals after which a status is considered stale.
+fn default_stale_after_polls() -> u64 {
+    2
+}
+
+impl Config {
+    /// Returns the interval between data polls (s) in effect at the given (UNIX) timestamp.
+    ///
+    /// When it is dark and polling continues at night, this is the night poll interval.
+    fn poll_interval_at(&self, timestamp: u64) -> u64 {
+        self.daylight
+            .as_ref()
+            .filter(|daylight| daylight.is_dark(timestamp))
+            .and_then(|daylight| daylight.night_poll_interval)
+            .unwrap_or(self.poll_interval)
+    }
+
+    /// Returns the age (s) after which the given status is considered stale at the given (UNIX)
+    /// timestamp.
+    ///
+    /// It uses the longer of the poll intervals in effect when the status was updated and at the
+    /// given timestamp, so that a status does not become stale when the poll interval changes
+    /// at sunrise or sunset.
+    fn stale_after_secs(&self, status: &Status, timestamp: u64) -> u64 {
+        let poll_interval = self
+            .poll_interval_at(status.last_updated)
+            .max(self.poll_interval_at(timestamp));
+
+        self.stale_after_polls.saturating_mul(poll_interval)
+    }
+
+    /// Returns all configured providers in the order they are tried.
+    ///
+    /// The (primary) provider is returned first, followed by the fallback providers.
+    fn providers(&self) -> Vec<ProviderConfig> {
+        let mut providers = Vec::with_capacity(self.fallback_providers.len() + 1);
+        providers.push(self.provider.clone());
+        providers.extend(self.fallback_providers.iter().cloned());
+
+        providers
+    }
+
+    /// Returns all configured accounts.
+    ///
+    /// If the single account is configured using the top-level site ID (and credentials), it is
+    /// returned first.
+    fn accounts(&self) -> Vec<AccountConfig> {
+        let mut accounts = Vec::with_capacity(self.accounts.len() + 1);
+        if let Some(site_id) = &self.site_id {
+            accounts.push(AccountConfig {
+                username: self.username.clone(),
+                password: self.password.clone(),
+                site_ids: vec![site_id.clone()],
+            });
+        }
+        accounts.extend(self.accounts.iter().cloned());
+
+        accounts
+    }
+}
+
+/// The configuration of an account to access the My Autarco site with.
+#[derive(Clone, Debug, Deserialize)]
+struct AccountConfig {
+    /// The username of the account to login with (only needed for the My Autarco provider)
+    username: Option<String>,
+    /// The password of the account to login with (only needed for the My Autarco provider)
+    password: Option<Secret>,
+    /// The Autarco site IDs to track
+    site_ids: Vec<String>,
 }
 
-/// The global, concurrently accessible current status.
-static STATUS: Lazy<Mutex<Option<Status>>> = Lazy::new(|| Mutex::new(None));
+/// A tracked Autarco site.
+#[derive(Debug)]
+struct Site {
+    /// The Autarco site ID
+    id: String,
+    /// The concurrently accessible and subscribable current status of the site
+    status: watch::Sender<Option<Status>>,
+}
+
+/// The global list of tracked sites.
+///
+/// The first site is the default site that is served at the top-level API endpoints.
+static SITES: OnceCell<Vec<Site>> = OnceCell::new();
+
+/// Returns the tracked site with the given site ID, or the default site if none is given.
+fn site(site_id: Option<&str>) -> Option<&'static Site> {
+    let sites = SITES.get()?;
+    match site_id {
+        Some(site_id) => sites.iter().find(|site| site.id == site_id),
+        None => sites.first(),
+    }
+}
+
+/// Returns the current (UNIX) timestamp.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The global list of states of the update loops (one per account).
+static UPDATERS: OnceCell<Vec<Arc<UpdaterState>>> = OnceCell::new();
+
+/// The global status history store (if enabled).
+static HISTORY: OnceCell<History> = OnceCell::new();
+
+/// The global snapshot of the last known statuses (if enabled).
+static SNAPSHOT: OnceCell<Snapshot> = OnceCell::new();
 
 /// The current photovoltaic invertor status.
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 struct Status {
     /// Current power production (W)
     current_w: u32,
+    /// Total energy produced today (kWh), if known
+    today_kwh: Option<u32>,
+    /// Total energy produced this month (kWh), if known
+    month_kwh: Option<u32>,
     /// Total energy produced since installation (kWh)
     total_kwh: u32,
     /// Timestamp of last update
     last_updated: u64,
+    /// The name of the provider that retrieved the status (if retrieved)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    /// The reason why the status was not retrieved but derived (if so)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<StatusReason>,
+    /// Whether the status is stale, e.g. because it was restored after a restart and not updated
+    /// since
+    #[serde(default)]
+    stale: bool,
 }
 
-/// Returns the current (last known) status.
+/// The reason why a status was not retrieved but derived.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum StatusReason {
+    /// It is dark, so no power is being produced and polling is paused
+    Darkness,
+}
+
+/// Returns the current (last known) status of the default site.
+#[get("/status", format = "application/json")]
+async fn status(_authenticated: Authenticated, config: &State<Config>) -> Option<StatusResponse> {
+    let status = site(None)?.status.borrow().clone();
+    status.map(|status| StatusResponse::new(status, config))
+}
+
+/// Returns the current (last known) status of the default site.
+///
+/// This is an alias of the `/api/v1/status` API endpoint for compatibility.
 #[get("/", format = "application/json")]
-async fn status() -> Option<Json<Status>> {
-    let status_guard = STATUS.lock().expect("Status mutex was poisoined");
-    status_guard.map(Json)
+async fn legacy_status(
+    authenticated: Authenticated,
+    config: &State<Config>,
+) -> Option<StatusResponse> {
+    status(authenticated, config).await
+}
+
+/// The current (last known) status of a tracked site.
+#[derive(Debug, JsonSchema, Serialize)]
+struct SiteStatus {
+    /// The Autarco site ID
+    site_id: String,
+    /// The current (last known) status, if any
+    status: Option<Status>,
+}
+
+/// Returns the tracked sites with their current (last known) status.
+#[get("/sites", format = "application/json")]
+async fn sites(_authenticated: Authenticated) -> Json<Vec<SiteStatus>> {
+    let site_statuses = SITES
+        .get()
+        .map(|sites| {
+            sites
+                .iter()
+                .map(|site| SiteStatus {
+                    site_id: site.id.clone(),
+                    status: site.status.borrow().clone(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Json(site_statuses)
+}
+
+/// Returns the current (last known) status of the site with the given site ID.
+#[get("/sites/<site_id>", format = "application/json")]
+async fn site_status(
+    site_id: &str,
+    _authenticated: Authenticated,
+    config: &State<Config>,
+) -> Option<StatusResponse> {
+    let status = site(Some(site_id))?.status.borrow().clone();
+    status.map(|status| StatusResponse::new(status, config))
 }
 
-/// Creates a Rocket and attaches the config parsing and update loop as fairings.
+/// Returns a stream of server-sent events with the current (last known) status of the given site.
+///
+/// An event is sent immediately if a status is known and each time the status is updated.
+fn events(site: &Site, mut shutdown: Shutdown) -> EventStream![] {
+    let mut receiver = site.status.subscribe();
+
+    EventStream! {
+        let status = receiver.borrow().clone();
+        if let Some(status) = status {
+            yield Event::json(&status).event("status");
+        }
+
+        loop {
+            select! {
+                result = receiver.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+
+            let status = receiver.borrow().clone();
+            if let Some(status) = status {
+                yield Event::json(&status).event("status");
+            }
+        }
+    }
+}
+
+/// Returns a stream of server-sent events with the status of the default site.
+#[get("/events")]
+async fn status_events(
+    _authenticated: Authenticated,
+    shutdown: Shutdown,
+) -> Option<EventStream![]> {
+    site(None).map(|site| events(site, shutdown))
+}
+
+/// Returns a stream of server-sent events with the status of the site with the given site ID.
+#[get("/sites/<site_id>/events")]
+async fn site_status_events(
+    site_id: &str,
+    _authenticated: Authenticated,
+    shutdown: Shutdown,
+) -> Option<EventStream![]> {
+    site(Some(site_id)).map(|site| events(site, shutdown))
+}
+
+/// Returns the status history of the given site between the given (UNIX) timestamps.
+///
+/// If `from` is omitted, the history starts at the first sample; if `to` is omitted, the history
+/// ends at the current time.
+fn history(
+    site: &Site,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<Option<Json<Vec<Status>>>, Debug<rusqlite::Error>> {
+    let history = match HISTORY.get() {
+        Some(history) => history,
+        None => return Ok(None),
+    };
+    let from = from.unwrap_or_default();
+    let to = to.unwrap_or_else(unix_now);
+    let statuses = history.range(&site.id, from, to)?;
+
+    Ok(Some(Json(statuses)))
+}
+
+/// Returns the status history of the default site between the given (UNIX) timestamps.
+#[get("/history?<from>&<to>", format = "application/json")]
+async fn status_history(
+    from: Option<u64>,
+    to: Option<u64>,
+    _authenticated: Authenticated,
+) -> Result<Option<Json<Vec<Status>>>, Debug<rusqlite::Error>> {
+    match site(None) {
+        Some(site) => history(site, from, to),
+        None => Ok(None),
+    }
+}
+
+/// Returns the status history of the site with the given site ID between the given (UNIX)
+/// timestamps.
+#[get("/sites/<site_id>/history?<from>&<to>", format = "application/json")]
+async fn site_status_history(
+    site_id: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    _authenticated: Authenticated,
+) -> Result<Option<Json<Vec<Status>>>, Debug<rusqlite::Error>> {
+    match site(Some(site_id)) {
+        Some(site) => history(site, from, to),
+        None => Ok(None),
+    }
+}
+
+/// Refreshes the status of the default site immediately and returns it.
+#[post("/refresh")]
+async fn refresh_status(_authorized: RefreshAuthorized) -> Result<Json<Status>, RefreshError> {
+    refresh::refresh(None).await
+}
+
+/// Refreshes the status of the site with the given site ID immediately and returns it.
+#[post("/sites/<site_id>/refresh")]
+async fn refresh_site_status(
+    site_id: &str,
+    _authorized: RefreshAuthorized,
+) -> Result<Json<Status>, RefreshError> {
+    refresh::refresh(Some(site_id)).await
+}
+
+/// The health of the service.
+#[derive(Debug, Serialize)]
+struct Health {
+    /// Whether the service is healthy
+    healthy: bool,
+    /// The states of the update loops
+    updaters: Vec<&'static UpdaterState>,
+    /// The freshness of the statuses of the tracked sites
+    sites: Vec<SiteFreshness>,
+}
+
+/// The freshness of the current status of a tracked site.
+#[derive(Debug, Serialize)]
+struct SiteFreshness {
+    /// The Autarco site ID
+    site_id: String,
+    /// The age of the current status (s), if any
+    age_secs: Option<u64>,
+    /// Whether the current status is not stale
+    fresh: bool,
+}
+
+/// Returns the health of the service.
+///
+/// If `ready` is set, the service is only healthy if all update loops are running (i.e. not
+/// backing off), all logins succeeded and all statuses are fresh, otherwise it is healthy as long
+/// as all update loops are supervised.
+fn health(config: &Config, ready: bool) -> (HttpStatus, Json<Health>) {
+    let timestamp = unix_now();
+    let updaters = UPDATERS
+        .get()
+        .map(|updaters| updaters.iter().map(AsRef::as_ref).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let sites = SITES
+        .get()
+        .map(|sites| {
+            sites
+                .iter()
+                .map(|site| {
+                    let status = site.status.borrow().clone();
+                    let age_secs = status
+                        .as_ref()
+                        .map(|status| timestamp.saturating_sub(status.last_updated));
+                    let fresh = status
+                        .as_ref()
+                        .zip(age_secs)
+                        .is_some_and(|(status, age_secs)| {
+                            !status.stale && age_secs <= config.stale_after_secs(status, timestamp)
+                        });
+                    SiteFreshness {
+                        site_id: site.id.clone(),
+                        age_secs,
+                        fresh,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let alive = !updaters.is_empty() && updaters.iter().all(|updater| updater.is_alive());
+    let healthy = if ready {
+        alive
+            && updaters
+                .iter()
+                .all(|updater| updater.is_running() && updater.is_logged_in())
+            && sites.iter().all(|site| site.fresh)
+    } else {
+        alive
+    };
+    let http_status = if healthy {
+        HttpStatus::Ok
+    } else {
+        HttpStatus::ServiceUnavailable
+    };
+
+    (
+        http_status,
+        Json(Health {
+            healthy,
+            updaters,
+            sites,
+        }),
+    )
+}
+
+/// Returns whether the service is alive, i.e. whether all update loops are supervised.
+#[get("/health", format = "application/json")]
+async fn liveness(config: &State<Config>) -> (HttpStatus, Json<Health>) {
+    health(config, false)
+}
+
+/// Returns whether the service is ready, i.e. whether all update loops are running, all logins
+/// succeeded and all statuses are fresh.
+#[get("/ready", format = "application/json")]
+async fn readiness(config: &State<Config>) -> (HttpStatus, Json<Health>) {
+    health(config, true)
+}
+
+/// Returns the most recent updater errors, newest first.
+#[get("/errors", format = "application/json")]
+async fn errors(_authenticated: Authenticated) -> Json<Vec<ErrorRecord>> {
+    Json(ERROR_LOG.records())
+}
+
+/// Returns the current status and updater metrics in the Prometheus text format.
+#[get("/metrics")]
+async fn prometheus_metrics(_authenticated: Authenticated) -> (ContentType, String) {
+    (ContentType::Plain, METRICS.render())
+}
+
+/// Returns the OpenAPI document describing the versioned API.
+#[get("/openapi.json")]
+async fn openapi_document(document: &State<OpenApiDocument>) -> Json<serde_json::Value> {
+    Json(document.0.clone())
+}
+
+/// Returns the Swagger UI page for exploring the versioned API.
+#[get("/docs")]
+async fn swagger_ui() -> RawHtml<&'static str> {
+    RawHtml(openapi::SWAGGER_UI)
+}
+
+/// Creates a Rocket and attaches the config parsing and update loops as fairings.
 #[rocket::launch]
 fn rocket() -> _ {
-    rocket::build()
-        .mount("/", routes![status])
+    let rocket = rocket::build();
+    // Set up logging before the rest of the configuration is extracted (on liftoff).
+    let log_format = match rocket.figment().extract_inner("log_format") {
+        Ok(log_format) => log_format,
+        Err(e) if e.missing() => logging::LogFormat::default(),
+        Err(e) => panic!("Invalid configuration: {e}"),
+    };
+    logging::init(log_format);
+
+    let routes = routes![
+        status,
+        status_events,
+        status_history,
+        sites,
+        site_status,
+        site_status_events,
+        site_status_history,
+        refresh_status,
+        refresh_site_status,
+        liveness,
+        readiness,
+        errors,
+        prometheus_metrics
+    ];
+
+    rocket
+        .mount(API_V1_BASE, routes.clone())
+        .mount(API_V1_BASE, routes![openapi_document, swagger_ui])
+        .mount("/", routes)
+        .mount("/", routes![legacy_status])
+        .register("/", catchers![auth::unauthorized])
+        .attach(AdHoc::on_ignite("OpenAPI document", |rocket| async {
+            let document = openapi::document(rocket.routes());
+            rocket.manage(OpenApiDocument(document))
+        }))
         .attach(AdHoc::config::<Config>())
+        .attach(Cors)
         .attach(AdHoc::on_liftoff("Updater", |rocket| {
             Box::pin(async move {
-                // We don't care about the join handle nor error results?
-                let config = rocket.figment().extract().expect("Invalid configuration");
-                let _ = rocket::tokio::spawn(update_loop(config));
+                let config: Arc<Config> = rocket
+                    .figment()
+                    .extract()
+                    .map(Arc::new)
+                    .expect("Invalid configuration");
+                if let Some(history_path) = &config.history_path {
+                    let history = History::open(history_path).expect("Cannot open history");
+                    let _ = HISTORY.set(history);
+                }
+
+                let accounts = config.accounts();
+                let sites = accounts
+                    .iter()
+                    .flat_map(|account| account.site_ids.iter())
+                    .map(|site_id| Site {
+                        id: site_id.clone(),
+                        status: watch::channel(None).0,
+                    })
+                    .collect::<Vec<_>>();
+                if sites.is_empty() {
+                    panic!("Invalid configuration: no sites configured");
+                }
+                let providers = config.providers();
+                let uses_my_autarco = providers
+                    .iter()
+                    .any(|provider| matches!(provider.kind, ProviderKind::MyAutarco));
+                let lacks_credentials = accounts
+                    .iter()
+                    .any(|account| account.username.is_none() || account.password.is_none());
+                if uses_my_autarco && lacks_credentials {
+                    panic!("Invalid configuration: the My Autarco provider needs credentials");
+                }
+                let has_duplicate_names = providers.iter().enumerate().any(|(index, provider)| {
+                    providers[..index]
+                        .iter()
+                        .any(|other| other.name() == provider.name())
+                });
+                if has_duplicate_names {
+                    panic!("Invalid configuration: the names of the providers must be unique");
+                }
+                let _ = SITES.set(sites);
+
+                if let Some(state_path) = &config.state_path {
+                    let snapshot = Snapshot::new(state_path);
+                    match snapshot.load() {
+                        Ok(statuses) => {
+                            for (site_id, status) in statuses {
+                                if let Some(site) = site(Some(&site_id)) {
+                                    let status = Status {
+                                        stale: true,
+                                        ..status
+                                    };
+                                    site.status.send_replace(Some(status));
+                                }
+                            }
+                        }
+                        Err(e) => warn!(error = %e, "Failed to restore statuses"),
+                    }
+                    let _ = SNAPSHOT.set(snapshot);
+                }
+
+                let site_ids = accounts
+                    .iter()
+                    .flat_map(|account| account.site_ids.iter().cloned())
+                    .collect::<Vec<_>>();
+                let mqtt_publisher = config
+                    .mqtt
+                    .as_ref()
+                    .map(|mqtt| Arc::new(MqttPublisher::new(mqtt, site_ids)));
+                let mut updaters = Vec::with_capacity(accounts.len());
+                for account in accounts {
+                    let state = Arc::new(UpdaterState::new(account.site_ids.clone()));
+                    updaters.push(Arc::clone(&state));
+
+                    rocket::tokio::spawn(supervise(
+                        Arc::clone(&config),
+                        account,
+                        mqtt_publisher.clone(),
+                        state,
+                    ));
+                }
+                let _ = UPDATERS.set(updaters);
+
+                if let Some(pvoutput) = &config.pvoutput {
+                    let uploader = PvOutputUploader::new(pvoutput, &config.backoff)
+                        .expect("Invalid PVOutput configuration");
+                    rocket::tokio::spawn(uploader.run());
+                }
             })
         }))
 }
@@ -0,0 +1,217 @@
+//! Module for collecting and exposing metrics in the Prometheus text format.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use super::provider::ProviderError;
+use super::Status;
+
+/// The global metrics of the status and the updater.
+pub(super) static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// The metrics of the status and the updater.
+#[derive(Debug)]
+pub(super) struct Metrics {
+    /// The registry all metrics are registered with
+    registry: Registry,
+    /// Current power production (W) by site
+    current_w: IntGaugeVec,
+    /// Total energy produced today (kWh) by site
+    today_kwh: IntGaugeVec,
+    /// Total energy produced this month (kWh) by site
+    month_kwh: IntGaugeVec,
+    /// Total energy produced since installation (kWh) by site
+    total_kwh: IntGaugeVec,
+    /// Timestamp of last update by site
+    last_updated: IntGaugeVec,
+    /// Number of statuses retrieved by site and source
+    source_updates: IntCounterVec,
+    /// Number of disagreements found when cross-checking by site and source
+    disagreements: IntCounterVec,
+    /// Number of polls performed
+    polls: IntCounter,
+    /// Number of failed polls by kind of failure
+    failures: IntCounterVec,
+    /// Number of (re-)logins performed
+    logins: IntCounter,
+    /// Latency of the requests to the My Autarco site by endpoint
+    upstream_latency: HistogramVec,
+    /// Duration of the last poll (s)
+    last_poll_duration: Gauge,
+}
+
+impl Metrics {
+    /// Creates and registers all metrics.
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("autarco")), None)
+            .expect("valid registry prefix");
+        let current_w = IntGaugeVec::new(
+            Opts::new("current_w", "Current power production (W)"),
+            &["site_id"],
+        )
+        .expect("valid metric");
+        let today_kwh = IntGaugeVec::new(
+            Opts::new("today_kwh", "Total energy produced today (kWh)"),
+            &["site_id"],
+        )
+        .expect("valid metric");
+        let month_kwh = IntGaugeVec::new(
+            Opts::new("month_kwh", "Total energy produced this month (kWh)"),
+            &["site_id"],
+        )
+        .expect("valid metric");
+        let total_kwh = IntGaugeVec::new(
+            Opts::new(
+                "total_kwh",
+                "Total energy produced since installation (kWh)",
+            ),
+            &["site_id"],
+        )
+        .expect("valid metric");
+        let last_updated = IntGaugeVec::new(
+            Opts::new("last_updated_timestamp_seconds", "Timestamp of last update"),
+            &["site_id"],
+        )
+        .expect("valid metric");
+        let source_updates = IntCounterVec::new(
+            Opts::new(
+                "source_updates_total",
+                "Number of statuses retrieved by source",
+            ),
+            &["site_id", "source"],
+        )
+        .expect("valid metric");
+        let disagreements = IntCounterVec::new(
+            Opts::new(
+                "source_disagreements_total",
+                "Number of disagreements found when cross-checking by source",
+            ),
+            &["site_id", "source"],
+        )
+        .expect("valid metric");
+        let polls =
+            IntCounter::new("polls_total", "Number of polls performed").expect("valid metric");
+        let failures = IntCounterVec::new(
+            Opts::new("poll_failures_total", "Number of failed polls by kind"),
+            &["kind"],
+        )
+        .expect("valid metric");
+        let logins = IntCounter::new("logins_total", "Number of (re-)logins performed")
+            .expect("valid metric");
+        let upstream_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_request_duration_seconds",
+                "Latency of the requests to the My Autarco site",
+            ),
+            &["endpoint"],
+        )
+        .expect("valid metric");
+        let last_poll_duration =
+            Gauge::new("last_poll_duration_seconds", "Duration of the last poll")
+                .expect("valid metric");
+
+        registry
+            .register(Box::new(current_w.clone()))
+            .and_then(|_| registry.register(Box::new(today_kwh.clone())))
+            .and_then(|_| registry.register(Box::new(month_kwh.clone())))
+            .and_then(|_| registry.register(Box::new(total_kwh.clone())))
+            .and_then(|_| registry.register(Box::new(last_updated.clone())))
+            .and_then(|_| registry.register(Box::new(source_updates.clone())))
+            .and_then(|_| registry.register(Box::new(disagreements.clone())))
+            .and_then(|_| registry.register(Box::new(polls.clone())))
+            .and_then(|_| registry.register(Box::new(failures.clone())))
+            .and_then(|_| registry.register(Box::new(logins.clone())))
+            .and_then(|_| registry.register(Box::new(upstream_latency.clone())))
+            .and_then(|_| registry.register(Box::new(last_poll_duration.clone())))
+            .expect("unique metrics");
+
+        Self {
+            registry,
+            current_w,
+            today_kwh,
+            month_kwh,
+            total_kwh,
+            last_updated,
+            source_updates,
+            disagreements,
+            polls,
+            failures,
+            logins,
+            upstream_latency,
+            last_poll_duration,
+        }
+    }
+
+    /// Records a newly retrieved status of the given site.
+    pub(super) fn observe_status(&self, site_id: &str, status: &Status) {
+        let labels = [site_id];
+        self.current_w
+            .with_label_values(&labels)
+            .set(i64::from(status.current_w));
+        if let Some(today_kwh) = status.today_kwh {
+            self.today_kwh
+                .with_label_values(&labels)
+                .set(i64::from(today_kwh));
+        }
+        if let Some(month_kwh) = status.month_kwh {
+            self.month_kwh
+                .with_label_values(&labels)
+                .set(i64::from(month_kwh));
+        }
+        self.total_kwh
+            .with_label_values(&labels)
+            .set(i64::from(status.total_kwh));
+        self.last_updated
+            .with_label_values(&labels)
+            .set(i64::try_from(status.last_updated).unwrap_or(i64::MAX));
+        if let Some(source) = &status.source {
+            self.source_updates
+                .with_label_values(&[site_id, source])
+                .inc();
+        }
+    }
+
+    /// Records a disagreement of the given source found when cross-checking a status of the given
+    /// site.
+    pub(super) fn observe_disagreement(&self, site_id: &str, source: &str) {
+        self.disagreements
+            .with_label_values(&[site_id, source])
+            .inc();
+    }
+
+    /// Records that a poll was performed that took the given duration (s).
+    pub(super) fn observe_poll(&self, duration: f64) {
+        self.polls.inc();
+        self.last_poll_duration.set(duration);
+    }
+
+    /// Records a failed poll with the given error.
+    pub(super) fn observe_failure(&self, error: &ProviderError) {
+        self.failures.with_label_values(&[error.kind()]).inc();
+    }
+
+    /// Records that a (re-)login was performed.
+    pub(super) fn observe_login(&self) {
+        self.logins.inc();
+    }
+
+    /// Records the latency (s) of a request to the given endpoint of the My Autarco site.
+    pub(super) fn observe_latency(&self, endpoint: &str, latency: f64) {
+        self.upstream_latency
+            .with_label_values(&[endpoint])
+            .observe(latency);
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub(super) fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encodable metrics");
+
+        String::from_utf8(buffer).expect("valid UTF-8 metrics")
+    }
+}
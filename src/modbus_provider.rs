@@ -0,0 +1,467 @@
+//! Module for retrieving statuses straight from an inverter or data logger via Modbus TCP.
+
+use std::io;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use autarco_scraper::LoginError;
+use rocket::tokio::net::lookup_host;
+use rocket::tokio::sync::Mutex as AsyncMutex;
+use rocket::tokio::time::timeout;
+use serde::Deserialize;
+use tokio_modbus::client::{tcp, Context, Reader};
+use tokio_modbus::slave::Slave;
+use tracing::field::Empty;
+use tracing::{debug, info, instrument, Span};
+
+use super::metrics::METRICS;
+use super::provider::{ProviderError, SolarProvider};
+use super::Status;
+
+/// The marker ("SunS") that identifies a SunSpec device at its base address.
+const SUNSPEC_MARKER: [u16; 2] = [0x5375, 0x6e53];
+
+/// The model ID that marks the end of the SunSpec models.
+const SUNSPEC_END_MODEL_ID: u16 = 0xffff;
+
+/// The IDs of the supported SunSpec inverter models (single phase, split phase and three phase).
+const SUNSPEC_INVERTER_MODEL_IDS: RangeInclusive<u16> = 101..=103;
+
+/// The value of a SunSpec signed integer or scale factor that is not implemented.
+const SUNSPEC_NOT_IMPLEMENTED: i16 = i16::MIN;
+
+/// The offset of the AC power (W) in the SunSpec inverter model.
+const SUNSPEC_W_OFFSET: u16 = 12;
+
+/// The offset of the scale factor of the AC power in the SunSpec inverter model.
+const SUNSPEC_W_SF_OFFSET: u16 = 13;
+
+/// The offset of the lifetime energy production (Wh) in the SunSpec inverter model.
+const SUNSPEC_WH_OFFSET: u16 = 22;
+
+/// The offset of the scale factor of the lifetime energy production in the SunSpec inverter
+/// model.
+const SUNSPEC_WH_SF_OFFSET: u16 = 24;
+
+/// The configuration of a Modbus TCP provider.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct ModbusProviderConfig {
+    /// The address (host and port) of the inverter or data logger
+    address: String,
+    /// The Modbus unit ID of the inverter
+    #[serde(default = "default_unit_id")]
+    unit_id: u8,
+    /// The timeout of connecting and of each request (s)
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    /// The map of the registers to read the values from
+    #[serde(default)]
+    registers: RegisterMap,
+}
+
+/// Returns the default Modbus unit ID.
+fn default_unit_id() -> u8 {
+    1
+}
+
+/// Returns the default timeout of connecting and of each request.
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Returns the default base address of the SunSpec models.
+fn default_sunspec_base_address() -> u16 {
+    40000
+}
+
+/// Returns the default multiplier of a register value.
+fn default_multiplier() -> f64 {
+    1.0
+}
+
+/// The map of the registers to read the values from.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "preset", rename_all = "snake_case")]
+pub(super) enum RegisterMap {
+    /// The SunSpec inverter model, which is discovered starting from the base address
+    Sunspec {
+        /// The base address of the SunSpec models
+        #[serde(default = "default_sunspec_base_address")]
+        base_address: u16,
+    },
+    /// The configured registers
+    Custom {
+        /// The register(s) of the current power production (W)
+        current_w: Register,
+        /// The register(s) of the energy produced today (kWh), if provided
+        today_kwh: Option<Register>,
+        /// The register(s) of the energy produced this month (kWh), if provided
+        month_kwh: Option<Register>,
+        /// The register(s) of the energy produced since installation (kWh)
+        total_kwh: Register,
+    },
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        RegisterMap::Sunspec {
+            base_address: default_sunspec_base_address(),
+        }
+    }
+}
+
+/// The configuration of the register(s) of a single value.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct Register {
+    /// The address of the (first) register
+    address: u16,
+    /// The table the register(s) are in
+    #[serde(default)]
+    table: RegisterTable,
+    /// The data type of the value; 32-bit values span two registers, high word first
+    #[serde(default, rename = "type")]
+    data_type: DataType,
+    /// The address of a register with the power of ten to scale the value with, if any
+    scale_factor: Option<u16>,
+    /// The factor to multiply the value with, e.g. to convert Wh into kWh
+    #[serde(default = "default_multiplier")]
+    multiplier: f64,
+}
+
+/// A Modbus register table.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum RegisterTable {
+    /// The holding registers (read using function code 3)
+    #[default]
+    Holding,
+    /// The input registers (read using function code 4)
+    Input,
+}
+
+/// The data type of a register value.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum DataType {
+    /// An unsigned 16-bit integer
+    #[default]
+    U16,
+    /// A signed 16-bit integer
+    I16,
+    /// An unsigned 32-bit integer
+    U32,
+    /// A signed 32-bit integer
+    I32,
+}
+
+impl DataType {
+    /// Returns the number of registers a value of the data type spans.
+    fn register_count(self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 => 2,
+        }
+    }
+
+    /// Decodes a value of the data type from the given register words.
+    fn decode(self, words: &[u16]) -> f64 {
+        let double_word = || (u32::from(words[0]) << 16) | u32::from(words[1]);
+
+        match self {
+            DataType::U16 => f64::from(words[0]),
+            DataType::I16 => f64::from(words[0] as i16),
+            DataType::U32 => f64::from(double_word()),
+            DataType::I32 => f64::from(double_word() as i32),
+        }
+    }
+}
+
+/// Error that can occur when reading values from a Modbus device.
+#[derive(Debug, thiserror::Error)]
+pub(super) enum ModbusError {
+    /// The address of the device could not be resolved
+    #[error("could not resolve address {0}")]
+    Resolve(String),
+    /// Connecting or communicating with the device failed, or it responded with an exception
+    #[error("Modbus I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The device did not respond in time
+    #[error("Modbus request timed out")]
+    Timeout,
+    /// The device does not provide SunSpec models at the base address
+    #[error("device does not provide SunSpec models at address {0}")]
+    NotSunSpec(u16),
+    /// The device does not provide a supported SunSpec inverter model
+    #[error("device does not provide a supported SunSpec inverter model")]
+    NoInverterModel,
+    /// The device does not implement the value at the given address
+    #[error("device does not implement the value at address {0}")]
+    NotImplemented(u16),
+}
+
+impl ModbusError {
+    /// Returns the kind of the Modbus error.
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            ModbusError::Resolve(_) => "modbus_resolve",
+            ModbusError::Io(_) => "modbus_io",
+            ModbusError::Timeout => "modbus_timeout",
+            ModbusError::NotSunSpec(_) => "modbus_not_sunspec",
+            ModbusError::NoInverterModel => "modbus_no_inverter_model",
+            ModbusError::NotImplemented(_) => "modbus_not_implemented",
+        }
+    }
+}
+
+/// Returns the factor for the given SunSpec scale factor read from the given address.
+fn scale(scale_factor: u16, address: u16) -> Result<f64, ModbusError> {
+    match scale_factor as i16 {
+        SUNSPEC_NOT_IMPLEMENTED => Err(ModbusError::NotImplemented(address)),
+        scale_factor => Ok(10f64.powi(i32::from(scale_factor))),
+    }
+}
+
+/// A connected Modbus device.
+#[derive(Debug)]
+struct Device {
+    /// The Modbus client context
+    context: Context,
+    /// The timeout of each request
+    timeout: Duration,
+    /// The address of the data of the SunSpec inverter model (if discovered)
+    inverter_model: Option<u16>,
+}
+
+impl Device {
+    /// Reads the given number of registers from the given table starting at the given address.
+    async fn read(
+        &mut self,
+        table: RegisterTable,
+        address: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, ModbusError> {
+        let request = match table {
+            RegisterTable::Holding => self.context.read_holding_registers(address, count),
+            RegisterTable::Input => self.context.read_input_registers(address, count),
+        };
+        let words = timeout(self.timeout, request)
+            .await
+            .map_err(|_| ModbusError::Timeout)??;
+        if words.len() != usize::from(count) {
+            return Err(ModbusError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected number of registers in response",
+            )));
+        }
+
+        Ok(words)
+    }
+
+    /// Reads the value of the given register(s).
+    async fn read_value(&mut self, register: &Register) -> Result<f64, ModbusError> {
+        let count = register.data_type.register_count();
+        let words = self.read(register.table, register.address, count).await?;
+        let mut value = register.data_type.decode(&words) * register.multiplier;
+        if let Some(address) = register.scale_factor {
+            let words = self.read(register.table, address, 1).await?;
+            value *= scale(words[0], address)?;
+        }
+
+        Ok(value)
+    }
+
+    /// Discovers the SunSpec inverter model by walking the models starting at the given base
+    /// address, and returns the address of its data.
+    async fn discover_inverter_model(&mut self, base_address: u16) -> Result<u16, ModbusError> {
+        let marker = self.read(RegisterTable::Holding, base_address, 2).await?;
+        if marker != SUNSPEC_MARKER {
+            return Err(ModbusError::NotSunSpec(base_address));
+        }
+
+        let mut address = base_address + 2;
+        loop {
+            let header = self.read(RegisterTable::Holding, address, 2).await?;
+            let (model_id, length) = (header[0], header[1]);
+            if SUNSPEC_INVERTER_MODEL_IDS.contains(&model_id) {
+                debug!(model_id, address, "Discovered SunSpec inverter model");
+                return Ok(address + 2);
+            }
+            if model_id == SUNSPEC_END_MODEL_ID {
+                return Err(ModbusError::NoInverterModel);
+            }
+
+            address = address
+                .checked_add(2 + length)
+                .ok_or(ModbusError::NoInverterModel)?;
+        }
+    }
+
+    /// Reads the current power production (W) and the lifetime energy production (kWh) from the
+    /// SunSpec inverter model, discovering it first if necessary.
+    async fn read_sunspec(&mut self, base_address: u16) -> Result<(f64, f64), ModbusError> {
+        let model_address = match self.inverter_model {
+            Some(model_address) => model_address,
+            None => {
+                let model_address = self.discover_inverter_model(base_address).await?;
+                self.inverter_model = Some(model_address);
+                model_address
+            }
+        };
+        let data = self
+            .read(
+                RegisterTable::Holding,
+                model_address,
+                SUNSPEC_WH_SF_OFFSET + 1,
+            )
+            .await?;
+        let word_at = |offset: u16| data[usize::from(offset)];
+
+        let power = match word_at(SUNSPEC_W_OFFSET) as i16 {
+            SUNSPEC_NOT_IMPLEMENTED => {
+                return Err(ModbusError::NotImplemented(
+                    model_address + SUNSPEC_W_OFFSET,
+                ))
+            }
+            power => f64::from(power),
+        };
+        let power_scale = scale(
+            word_at(SUNSPEC_W_SF_OFFSET),
+            model_address + SUNSPEC_W_SF_OFFSET,
+        )?;
+        let energy = DataType::U32.decode(&data[usize::from(SUNSPEC_WH_OFFSET)..]);
+        let energy_scale = scale(
+            word_at(SUNSPEC_WH_SF_OFFSET),
+            model_address + SUNSPEC_WH_SF_OFFSET,
+        )?;
+
+        Ok((power * power_scale, energy * energy_scale / 1000.0))
+    }
+
+    /// Reads the status using the given register map.
+    async fn read_status(
+        &mut self,
+        registers: &RegisterMap,
+        last_updated: u64,
+    ) -> Result<Status, ModbusError> {
+        let (current_w, today_kwh, month_kwh, total_kwh) = match registers {
+            RegisterMap::Sunspec { base_address } => {
+                let (current_w, total_kwh) = self.read_sunspec(*base_address).await?;
+                (current_w, None, None, total_kwh)
+            }
+            RegisterMap::Custom {
+                current_w,
+                today_kwh,
+                month_kwh,
+                total_kwh,
+            } => {
+                let current_w = self.read_value(current_w).await?;
+                let today_kwh = match today_kwh {
+                    Some(register) => Some(self.read_value(register).await?),
+                    None => None,
+                };
+                let month_kwh = match month_kwh {
+                    Some(register) => Some(self.read_value(register).await?),
+                    None => None,
+                };
+                let total_kwh = self.read_value(total_kwh).await?;
+                (current_w, today_kwh, month_kwh, total_kwh)
+            }
+        };
+
+        Ok(Status {
+            current_w: current_w.round() as u32,
+            today_kwh: today_kwh.map(|value| value.round() as u32),
+            month_kwh: month_kwh.map(|value| value.round() as u32),
+            total_kwh: total_kwh.round() as u32,
+            last_updated,
+            source: None,
+            reason: None,
+            stale: false,
+        })
+    }
+}
+
+/// A provider that reads statuses straight from an inverter or data logger via Modbus TCP.
+///
+/// The connection is kept open between polls and is reestablished after a failure.
+#[derive(Debug)]
+pub(super) struct ModbusProvider {
+    /// The configuration of the provider
+    config: ModbusProviderConfig,
+    /// The connected device (if connected)
+    device: AsyncMutex<Option<Device>>,
+}
+
+impl ModbusProvider {
+    /// Creates a provider with the given configuration.
+    pub(super) fn new(config: &ModbusProviderConfig) -> Self {
+        Self {
+            config: config.clone(),
+            device: AsyncMutex::new(None),
+        }
+    }
+
+    /// Connects to the device.
+    async fn connect(&self) -> Result<Device, ModbusError> {
+        let address = lookup_host(&self.config.address)
+            .await?
+            .next()
+            .ok_or_else(|| ModbusError::Resolve(self.config.address.clone()))?;
+        let timeout_duration = Duration::from_secs(self.config.timeout);
+        let context = timeout(
+            timeout_duration,
+            tcp::connect_slave(address, Slave(self.config.unit_id)),
+        )
+        .await
+        .map_err(|_| ModbusError::Timeout)??;
+        info!(%address, unit_id = self.config.unit_id, "Connected to Modbus device");
+
+        Ok(Device {
+            context,
+            timeout: timeout_duration,
+            inverter_model: None,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl SolarProvider for ModbusProvider {
+    /// Does nothing, because no session is needed; the device is connected to when polling.
+    async fn login(&self) -> Result<(), LoginError> {
+        Ok(())
+    }
+
+    /// Reads the status from the device using the configured register map.
+    ///
+    /// The site ID is only used to identify the site; all sites are read from the same device.
+    #[instrument(
+        skip_all,
+        fields(site_id = %site_id, endpoint = "modbus", latency_ms = Empty)
+    )]
+    async fn fetch_status(
+        &self,
+        site_id: &str,
+        last_updated: u64,
+    ) -> Result<Status, ProviderError> {
+        let mut slot = self.device.lock().await;
+        let start = Instant::now();
+        let mut device = match slot.take() {
+            Some(device) => device,
+            None => self.connect().await?,
+        };
+        let result = device
+            .read_status(&self.config.registers, last_updated)
+            .await;
+        let latency = start.elapsed();
+        Span::current().record("latency_ms", latency.as_millis() as u64);
+        METRICS.observe_latency("modbus", latency.as_secs_f64());
+        debug!("Read status data");
+
+        // Keep the connection only if it is still known to be usable.
+        if result.is_ok() {
+            *slot = Some(device);
+        }
+
+        Ok(result?)
+    }
+}
@@ -0,0 +1,236 @@
+//! Module for publishing the status to an MQTT broker, including Home Assistant discovery.
+
+use std::time::Duration;
+
+use rocket::tokio::{self, time::sleep};
+use rumqttc::{AsyncClient, ClientError, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::warn;
+
+use super::{Secret, Status};
+
+/// The configuration necessary to publish to an MQTT broker.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct MqttConfig {
+    /// The host name of the MQTT broker
+    host: String,
+    /// The port of the MQTT broker
+    #[serde(default = "default_port")]
+    port: u16,
+    /// The username to authenticate with at the MQTT broker (if any)
+    username: Option<String>,
+    /// The password to authenticate with at the MQTT broker (if any)
+    password: Option<Secret>,
+    /// The client ID to use
+    #[serde(default = "default_client_id")]
+    client_id: String,
+    /// The prefix of the topic to publish the status on
+    #[serde(default = "default_topic_prefix")]
+    topic_prefix: String,
+    /// The prefix of the topics to publish the Home Assistant discovery configuration on
+    #[serde(default = "default_discovery_prefix")]
+    discovery_prefix: String,
+}
+
+/// Returns the default MQTT broker port.
+fn default_port() -> u16 {
+    1883
+}
+
+/// Returns the default MQTT client ID.
+fn default_client_id() -> String {
+    String::from("autarco-scraper")
+}
+
+/// Returns the default prefix of the status topic.
+fn default_topic_prefix() -> String {
+    String::from("autarco")
+}
+
+/// Returns the default prefix of the Home Assistant discovery topics.
+fn default_discovery_prefix() -> String {
+    String::from("homeassistant")
+}
+
+/// The sensors that are announced to Home Assistant.
+///
+/// Each sensor consists of the status field, its name, device class, unit and state class.
+const SENSORS: [(&str, &str, &str, &str, &str); 4] = [
+    ("current_w", "Current power", "power", "W", "measurement"),
+    (
+        "today_kwh",
+        "Energy today",
+        "energy",
+        "kWh",
+        "total_increasing",
+    ),
+    (
+        "month_kwh",
+        "Energy this month",
+        "energy",
+        "kWh",
+        "total_increasing",
+    ),
+    (
+        "total_kwh",
+        "Energy total",
+        "energy",
+        "kWh",
+        "total_increasing",
+    ),
+];
+
+/// Publisher of the status of sites to an MQTT broker.
+#[derive(Debug)]
+pub(super) struct MqttPublisher {
+    /// The MQTT client
+    client: AsyncClient,
+    /// The prefix of the topic to publish the status on
+    topic_prefix: String,
+}
+
+impl MqttPublisher {
+    /// Creates a publisher that connects to the configured MQTT broker for the given sites.
+    ///
+    /// The connection is handled by a spawned task that keeps reconnecting to the broker if the
+    /// connection gets lost. Each time it has (re)connected, and each time Home Assistant comes
+    /// online, it announces the sensors of the sites to Home Assistant.
+    pub(super) fn new(config: &MqttConfig, site_ids: Vec<String>) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password.expose());
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, 10);
+        let discovery = Discovery {
+            client: client.clone(),
+            topic_prefix: config.topic_prefix.clone(),
+            discovery_prefix: config.discovery_prefix.clone(),
+            site_ids,
+        };
+        let birth_topic = discovery.birth_topic();
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    // The broker may have lost the retained configurations, e.g. after a restart
+                    // without persistence, so announce the sensors on every (re)connect.
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        tokio::spawn(discovery.clone().announce(true));
+                    }
+                    // Home Assistant has (re)started and needs the configurations again.
+                    Ok(Event::Incoming(Packet::Publish(publish)))
+                        if publish.topic == birth_topic
+                            && publish.payload.as_ref() == b"online" =>
+                    {
+                        tokio::spawn(discovery.clone().announce(false));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!(error = %e, "MQTT connection error");
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            topic_prefix: config.topic_prefix.clone(),
+        }
+    }
+
+    /// Publishes the given status of the given site.
+    ///
+    /// It never waits for the broker, so that an unreachable broker cannot hold up the updater;
+    /// if the outgoing queue is full, an error is returned and the status is dropped instead.
+    pub(super) fn publish_status(&self, site_id: &str, status: &Status) -> Result<(), ClientError> {
+        let payload = serde_json::to_string(status).expect("serializable status");
+        self.client.try_publish(
+            state_topic(&self.topic_prefix, site_id),
+            QoS::AtLeastOnce,
+            false,
+            payload,
+        )
+    }
+}
+
+/// Returns the topic to publish the status of the given site on.
+fn state_topic(topic_prefix: &str, site_id: &str) -> String {
+    format!("{}/{}/state", topic_prefix, site_id)
+}
+
+/// The announcer of the sensors of the sites to Home Assistant using MQTT discovery.
+#[derive(Clone, Debug)]
+struct Discovery {
+    /// The MQTT client
+    client: AsyncClient,
+    /// The prefix of the topic to publish the status on
+    topic_prefix: String,
+    /// The prefix of the topics to publish the Home Assistant discovery configuration on
+    discovery_prefix: String,
+    /// The IDs of the sites to announce the sensors of
+    site_ids: Vec<String>,
+}
+
+impl Discovery {
+    /// Returns the topic Home Assistant publishes its birth message on.
+    fn birth_topic(&self) -> String {
+        format!("{}/status", self.discovery_prefix)
+    }
+
+    /// Announces the sensors of all sites, after subscribing to the birth message of Home
+    /// Assistant first if requested.
+    ///
+    /// It waits until the outgoing queue has room, so it has to run in its own task.
+    async fn announce(self, subscribe: bool) {
+        if subscribe {
+            let result = self
+                .client
+                .subscribe(self.birth_topic(), QoS::AtLeastOnce)
+                .await;
+            if let Err(e) = result {
+                warn!(error = %e, "Failed to subscribe to the Home Assistant status");
+            }
+        }
+
+        for site_id in &self.site_ids {
+            if let Err(e) = self.publish(site_id).await {
+                warn!(error = %e, "Failed to publish MQTT discovery configuration");
+            }
+        }
+    }
+
+    /// Publishes the (retained) Home Assistant discovery configuration for all sensors of the
+    /// given site.
+    async fn publish(&self, site_id: &str) -> Result<(), ClientError> {
+        let device_id = format!("autarco_{}", site_id);
+        let state_topic = state_topic(&self.topic_prefix, site_id);
+        for (field, name, device_class, unit, state_class) in SENSORS {
+            let topic = format!(
+                "{}/sensor/{}/{}/config",
+                self.discovery_prefix, device_id, field
+            );
+            let payload = json!({
+                "name": name,
+                "unique_id": format!("{}_{}", device_id, field),
+                "state_topic": state_topic,
+                "value_template": format!("{{{{ value_json.{} }}}}", field),
+                "device_class": device_class,
+                "unit_of_measurement": unit,
+                "state_class": state_class,
+                "device": {
+                    "identifiers": [device_id],
+                    "name": format!("Autarco {}", site_id),
+                    "manufacturer": "Autarco",
+                },
+            });
+            self.client
+                .publish(topic, QoS::AtLeastOnce, true, payload.to_string())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
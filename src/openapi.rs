@@ -0,0 +1,338 @@
+//! Module for describing the versioned API using an OpenAPI document.
+
+use rocket::Route;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use super::error_log::ErrorRecord;
+use super::freshness::FreshStatus;
+use super::{SiteStatus, Status};
+
+/// The base path the versioned API is mounted at.
+pub(super) const API_V1_BASE: &str = "/api/v1";
+
+/// The OpenAPI document describing the versioned API, generated once the routes are mounted.
+pub(super) struct OpenApiDocument(pub(super) Value);
+
+/// The Swagger UI page that renders the OpenAPI document.
+pub(super) const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8">
+    <title>Autarco Scraper API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4/swagger-ui.css">
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@4/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+/// Returns a JSON response description with the given schema.
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } }
+    })
+}
+
+/// Returns the (reference to the) schema for the given type, registering it with the generator.
+fn schema_for<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(generator.subschema_for::<T>()).unwrap_or_default()
+}
+
+/// Converts a Rocket route path (e.g. `/sites/<site_id>`) into an OpenAPI path (`/sites/{site_id}`).
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(
+            |segment| match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                Some(name) => format!("{{{}}}", name.trim_end_matches("..")),
+                None => segment.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Generates the OpenAPI 3 document describing the versioned API.
+///
+/// The paths are derived from the given routes that are mounted at [`API_V1_BASE`], so that the
+/// document never lists paths that are not served. Routes without a description are listed with a
+/// default response only.
+pub(super) fn document<'a>(routes: impl Iterator<Item = &'a Route>) -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let fresh_status = schema_for::<FreshStatus>(&mut generator);
+    let status = schema_for::<Status>(&mut generator);
+    let statuses = schema_for::<Vec<Status>>(&mut generator);
+    let site_statuses = schema_for::<Vec<SiteStatus>>(&mut generator);
+    let error_records = schema_for::<Vec<ErrorRecord>>(&mut generator);
+    let schemas = serde_json::to_value(generator.take_definitions()).unwrap_or_default();
+
+    let site_id = json!({
+        "name": "site_id",
+        "in": "path",
+        "required": true,
+        "description": "The Autarco site ID",
+        "schema": { "type": "string" }
+    });
+    let range = json!([
+        {
+            "name": "from",
+            "in": "query",
+            "description": "The (UNIX) timestamp to start from, defaults to the beginning",
+            "schema": { "type": "integer", "format": "uint64" }
+        },
+        {
+            "name": "to",
+            "in": "query",
+            "description": "The (UNIX) timestamp to end at, defaults to the current time",
+            "schema": { "type": "integer", "format": "uint64" }
+        }
+    ]);
+    let with_site_id = |parameters: &Value| {
+        let mut parameters = parameters.as_array().cloned().unwrap_or_default();
+        parameters.insert(0, site_id.clone());
+        Value::Array(parameters)
+    };
+    let events = json!({
+        "description": "A stream of server-sent events with the current status",
+        "content": { "text/event-stream": { "schema": { "type": "string" } } }
+    });
+    let health = json_response(
+        "The health of the service, including the states of the update loops",
+        json!({ "type": "object" }),
+    );
+    let not_found = json!({ "description": "The site or its status is not known (yet)" });
+    let not_modified = json!({ "description": "The status has not been modified" });
+    let unhealthy = json!({ "description": "The service is not healthy" });
+
+    let mut operations = Map::new();
+    operations.insert(
+        String::from("/status"),
+        json!({
+            "get": {
+                "summary": "Returns the current (last known) status of the default site",
+                "responses": {
+                    "200": json_response("The current status", fresh_status.clone()),
+                    "304": not_modified,
+                    "404": not_found
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/sites"),
+        json!({
+            "get": {
+                "summary": "Returns the tracked sites with their current (last known) status",
+                "responses": {
+                    "200": json_response("The tracked sites", site_statuses)
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/sites/{site_id}"),
+        json!({
+            "get": {
+                "summary": "Returns the current (last known) status of a site",
+                "parameters": [site_id],
+                "responses": {
+                    "200": json_response("The current status", fresh_status),
+                    "304": not_modified,
+                    "404": not_found
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/events"),
+        json!({
+            "get": {
+                "summary": "Returns a stream of status events of the default site",
+                "responses": { "200": events, "404": not_found }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/sites/{site_id}/events"),
+        json!({
+            "get": {
+                "summary": "Returns a stream of status events of a site",
+                "parameters": [site_id],
+                "responses": { "200": events, "404": not_found }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/history"),
+        json!({
+            "get": {
+                "summary": "Returns the status history of the default site",
+                "parameters": range,
+                "responses": {
+                    "200": json_response("The statuses in the time range", statuses.clone()),
+                    "404": { "description": "The history is not enabled" }
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/sites/{site_id}/history"),
+        json!({
+            "get": {
+                "summary": "Returns the status history of a site",
+                "parameters": with_site_id(&range),
+                "responses": {
+                    "200": json_response("The statuses in the time range", statuses),
+                    "404": { "description": "The site is unknown or history is not enabled" }
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/refresh"),
+        json!({
+            "post": {
+                "summary": "Refreshes the status of the default site immediately",
+                "responses": {
+                    "200": json_response("The refreshed status", status.clone()),
+                    "403": { "description": "Refreshing is disabled" },
+                    "429": { "description": "The last attempt failed too recently" },
+                    "502": { "description": "Retrieving the status failed" }
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/sites/{site_id}/refresh"),
+        json!({
+            "post": {
+                "summary": "Refreshes the status of a site immediately",
+                "parameters": [site_id],
+                "responses": {
+                    "200": json_response("The refreshed status", status),
+                    "403": { "description": "Refreshing is disabled" },
+                    "404": { "description": "The site is unknown" },
+                    "429": { "description": "The last attempt failed too recently" },
+                    "502": { "description": "Retrieving the status failed" }
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/health"),
+        json!({
+            "get": {
+                "summary": "Returns whether the service is alive",
+                "security": [],
+                "responses": { "200": health, "503": unhealthy }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/ready"),
+        json!({
+            "get": {
+                "summary": "Returns whether the service is ready",
+                "security": [],
+                "responses": { "200": health, "503": unhealthy }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/errors"),
+        json!({
+            "get": {
+                "summary": "Returns the most recent updater errors, newest first",
+                "responses": {
+                    "200": json_response("The most recent errors", error_records)
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/metrics"),
+        json!({
+            "get": {
+                "summary": "Returns the metrics in the Prometheus text format",
+                "responses": {
+                    "200": {
+                        "description": "The metrics",
+                        "content": { "text/plain": { "schema": { "type": "string" } } }
+                    }
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/openapi.json"),
+        json!({
+            "get": {
+                "summary": "Returns this OpenAPI document",
+                "security": [],
+                "responses": {
+                    "200": json_response("The OpenAPI document", json!({ "type": "object" }))
+                }
+            }
+        }),
+    );
+    operations.insert(
+        String::from("/docs"),
+        json!({
+            "get": {
+                "summary": "Returns the Swagger UI page for exploring the API",
+                "security": [],
+                "responses": {
+                    "200": {
+                        "description": "The Swagger UI page",
+                        "content": { "text/html": { "schema": { "type": "string" } } }
+                    }
+                }
+            }
+        }),
+    );
+
+    let mut paths = Map::new();
+    for route in routes.filter(|route| route.uri.base() == API_V1_BASE) {
+        let path = openapi_path(route.uri.unmounted_origin.path().as_str());
+        let method = route.method.as_str().to_ascii_lowercase();
+        let mut operation = operations
+            .get(&path)
+            .and_then(|item| item.get(&method))
+            .cloned()
+            .unwrap_or_else(
+                || json!({ "responses": { "default": { "description": "The response" } } }),
+            );
+        // All operations require authentication (if enabled) unless they opt out of security.
+        if operation.get("security") != Some(&json!([])) {
+            operation["responses"]["401"] = json!({ "description": "Authentication is required" });
+        }
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Autarco Scraper",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "servers": [{ "url": API_V1_BASE }],
+        "security": [{ "bearer": [] }, { "basic": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "basic": { "type": "http", "scheme": "basic" }
+            }
+        }
+    })
+}
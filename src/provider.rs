@@ -0,0 +1,197 @@
+//! Module for the providers of solar panel statuses, such as the My Autarco site.
+
+use std::fmt;
+
+use autarco_scraper::{AutarcoClient, LoginError, UpdateError};
+use serde::Deserialize;
+
+use super::json_provider::JsonProviderConfig;
+use super::modbus_provider::{ModbusError, ModbusProviderConfig};
+use super::Status;
+
+/// The configuration of a provider to retrieve the statuses from.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(super) struct ProviderConfig {
+    /// The name of the provider, recorded as the source of the statuses it retrieves (defaults
+    /// to the type of the provider)
+    name: Option<String>,
+    /// The type of the provider and its type-specific configuration
+    #[serde(flatten)]
+    pub(super) kind: ProviderKind,
+}
+
+impl ProviderConfig {
+    /// Returns the name of the provider.
+    pub(super) fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_else(|| self.kind.as_str())
+    }
+}
+
+/// The type of a provider to retrieve the statuses from, with its type-specific configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum ProviderKind {
+    /// The My Autarco site
+    #[default]
+    MyAutarco,
+    /// A generic JSON-over-HTTP API
+    Json(JsonProviderConfig),
+    /// An inverter or data logger via Modbus TCP
+    Modbus(ModbusProviderConfig),
+}
+
+impl ProviderKind {
+    /// Returns the name of the type of provider.
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::MyAutarco => "my_autarco",
+            ProviderKind::Json(_) => "json",
+            ProviderKind::Modbus(_) => "modbus",
+        }
+    }
+}
+
+/// The configuration of cross-checking a status with the statuses of the next providers.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub(super) struct CrossCheckConfig {
+    /// The maximum difference in power production (W) that is not considered a disagreement
+    #[serde(default = "default_max_power_difference_w")]
+    max_power_difference_w: u32,
+    /// The maximum difference in energy production (kWh) that is not considered a disagreement
+    #[serde(default = "default_max_energy_difference_kwh")]
+    max_energy_difference_kwh: u32,
+}
+
+/// Returns the default maximum difference in power production that is not a disagreement.
+fn default_max_power_difference_w() -> u32 {
+    100
+}
+
+/// Returns the default maximum difference in energy production that is not a disagreement.
+fn default_max_energy_difference_kwh() -> u32 {
+    1
+}
+
+impl CrossCheckConfig {
+    /// Returns descriptions of the values of the given statuses that disagree.
+    ///
+    /// The energy produced today and this month are only compared if both statuses have them.
+    pub(super) fn disagreements(&self, status: &Status, other: &Status) -> Vec<String> {
+        let values = [
+            ("current_w", Some(status.current_w), Some(other.current_w)),
+            ("today_kwh", status.today_kwh, other.today_kwh),
+            ("month_kwh", status.month_kwh, other.month_kwh),
+            ("total_kwh", Some(status.total_kwh), Some(other.total_kwh)),
+        ];
+
+        values
+            .into_iter()
+            .filter_map(|(name, value, other_value)| {
+                let (value, other_value) = value.zip(other_value)?;
+                let max_difference = if name == "current_w" {
+                    self.max_power_difference_w
+                } else {
+                    self.max_energy_difference_kwh
+                };
+
+                (value.abs_diff(other_value) > max_difference)
+                    .then(|| format!("{} is {} vs. {}", name, value, other_value))
+            })
+            .collect()
+    }
+}
+
+/// Error that can occur when retrieving a status from a provider.
+#[derive(Debug, thiserror::Error)]
+pub(super) enum ProviderError {
+    /// Logging in (again) failed
+    #[error(transparent)]
+    Login(#[from] LoginError),
+    /// Retrieving the status failed
+    #[error(transparent)]
+    Update(#[from] UpdateError),
+    /// The response does not contain a (numeric) value at the given JSON pointer
+    #[error("response lacks a numeric value at {0}")]
+    MissingValue(String),
+    /// Reading the values from a Modbus device failed
+    #[error(transparent)]
+    Modbus(#[from] ModbusError),
+}
+
+impl ProviderError {
+    /// Returns the kind of the provider error.
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::Login(e) => e.kind(),
+            ProviderError::Update(e) => e.kind(),
+            ProviderError::MissingValue(_) => "missing_value",
+            ProviderError::Modbus(e) => e.kind(),
+        }
+    }
+}
+
+impl From<autarco_scraper::Error> for ProviderError {
+    fn from(error: autarco_scraper::Error) -> Self {
+        match error {
+            autarco_scraper::Error::Login(e) => ProviderError::Login(e),
+            autarco_scraper::Error::Update(e) => ProviderError::Update(e),
+        }
+    }
+}
+
+/// A provider of the statuses of solar panel sites.
+#[rocket::async_trait]
+pub(super) trait SolarProvider: fmt::Debug + Send + Sync {
+    /// Logs in on the provider, if it needs a session.
+    async fn login(&self) -> Result<(), LoginError>;
+
+    /// Renews the session of the provider after retrieving a status was not authorized.
+    ///
+    /// By default, it does nothing, for providers that do not need a session or that renew it
+    /// themselves.
+    async fn refresh(&self) -> Result<(), LoginError> {
+        Ok(())
+    }
+
+    /// Retrieves the current status of the given site, updated at the given (UNIX) timestamp.
+    ///
+    /// The source of the status is left empty; it is set to the name of the provider by the
+    /// caller.
+    async fn fetch_status(&self, site_id: &str, last_updated: u64)
+        -> Result<Status, ProviderError>;
+}
+
+#[rocket::async_trait]
+impl SolarProvider for AutarcoClient {
+    async fn login(&self) -> Result<(), LoginError> {
+        AutarcoClient::login(self).await
+    }
+
+    /// Renews the session by logging in again.
+    async fn refresh(&self) -> Result<(), LoginError> {
+        AutarcoClient::login(self).await
+    }
+
+    /// Retrieves the current status using both the `energy` and `power` KPI endpoints.
+    ///
+    /// If the session has expired, the client logs in again.
+    async fn fetch_status(
+        &self,
+        site_id: &str,
+        last_updated: u64,
+    ) -> Result<Status, ProviderError> {
+        let energy = self.energy(site_id).await?;
+        let power = self.power(site_id).await?;
+
+        Ok(Status {
+            current_w: power.pv_now,
+            today_kwh: energy.pv_today,
+            month_kwh: energy.pv_month,
+            total_kwh: energy.pv_to_date,
+            last_updated,
+            source: None,
+            reason: None,
+            stale: false,
+        })
+    }
+}
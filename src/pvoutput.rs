@@ -0,0 +1,451 @@
+//! Module for uploading the statuses to PVOutput.org.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use rocket::tokio::select;
+use rocket::tokio::time::sleep;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use time_tz::{timezones, OffsetDateTimeExt, Tz};
+use tracing::{debug, info, instrument, warn};
+
+use super::atomic_file;
+use super::supervisor::{Backoff, BackoffConfig};
+use super::{site, unix_now, Secret, Status};
+
+/// The maximum number of samples that can be uploaded in a single batch.
+const MAX_BATCH_SIZE: usize = 30;
+
+/// The window the PVOutput rate limit applies to (s).
+const RATE_LIMIT_WINDOW_SECS: u64 = 3600;
+
+/// The configuration of uploading the statuses to PVOutput.org.
+#[derive(Clone, Debug, Deserialize)]
+pub(super) struct PvOutputConfig {
+    /// The base URL of the PVOutput API
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// The API key to upload with
+    api_key: Secret,
+    /// The ID of the system to upload the statuses to
+    system_id: String,
+    /// The Autarco site ID of which to upload the statuses (defaults to the default site)
+    site_id: Option<String>,
+    /// The (IANA) time zone of the system, e.g. `Europe/Amsterdam`
+    timezone: String,
+    /// The status interval of the system (min)
+    #[serde(default = "default_status_interval")]
+    status_interval: u64,
+    /// The maximum number of requests per hour
+    #[serde(default = "default_max_requests_per_hour")]
+    max_requests_per_hour: usize,
+    /// The maximum age of samples that are still uploaded (days)
+    #[serde(default = "default_max_backfill_days")]
+    max_backfill_days: u64,
+    /// The path of the file to persist the queue of samples to upload in (if enabled)
+    queue_path: Option<PathBuf>,
+}
+
+/// Returns the default base URL of the PVOutput API.
+fn default_base_url() -> String {
+    String::from("https://pvoutput.org")
+}
+
+/// Returns the default status interval of a system.
+fn default_status_interval() -> u64 {
+    5
+}
+
+/// Returns the default maximum number of requests per hour.
+fn default_max_requests_per_hour() -> usize {
+    60
+}
+
+/// Returns the default maximum age of samples that are still uploaded.
+fn default_max_backfill_days() -> u64 {
+    14
+}
+
+/// A sample of a status to upload, aligned to the status interval of the system.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct Sample {
+    /// The (UNIX) timestamp of the start of the status interval
+    timestamp: u64,
+    /// The energy produced since installation (Wh)
+    energy_wh: u64,
+    /// The current power production (W)
+    power_w: u32,
+}
+
+/// Error that can occur when uploading samples to PVOutput.
+#[derive(Debug, thiserror::Error)]
+enum UploadError {
+    /// The request could not be performed
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    /// The samples were rejected, e.g. because they are too old or have invalid values
+    #[error("samples were rejected: {0}")]
+    Rejected(String),
+    /// The rate limit has been exceeded until the given (UNIX) timestamp (if known)
+    #[error("rate limit exceeded")]
+    RateLimited(Option<u64>),
+    /// The response has an unexpected HTTP status
+    #[error("unexpected HTTP status {0}: {1}")]
+    HttpStatus(StatusCode, String),
+}
+
+/// Loads the queue of samples from the file at the given path.
+///
+/// If the file does not exist yet, the queue is empty.
+fn load_queue(path: &Path) -> io::Result<VecDeque<Sample>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(VecDeque::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves the given queue of samples to the file at the given path.
+///
+/// The file is replaced atomically, so that it is never left partially written.
+fn write_queue(path: &Path, queue: &VecDeque<Sample>) -> io::Result<()> {
+    let contents = serde_json::to_vec(queue)?;
+
+    atomic_file::write(path, &contents)
+}
+
+/// Uploader of the statuses of a site to a PVOutput system.
+///
+/// The statuses are sampled once per status interval of the system. Samples that could not be
+/// uploaded are queued and uploaded in batches once possible, within the rate limit.
+#[derive(Debug)]
+pub(super) struct PvOutputUploader {
+    /// The configuration
+    config: PvOutputConfig,
+    /// The time zone of the system
+    timezone: &'static Tz,
+    /// The HTTP client
+    client: Client,
+    /// The samples that still need to be uploaded, oldest first
+    queue: VecDeque<Sample>,
+    /// The (UNIX) timestamp of the last uploaded sample
+    last_uploaded: u64,
+    /// The (UNIX) timestamps of the requests within the rate limit window, oldest first
+    requests: VecDeque<u64>,
+    /// The (UNIX) timestamp before which no upload is attempted after a failure
+    retry_at: u64,
+    /// The backoff for failed uploads
+    backoff: Backoff,
+}
+
+impl PvOutputUploader {
+    /// Creates an uploader with the given configuration and the given backoff configuration.
+    ///
+    /// If a queue file is configured, the samples that were not uploaded yet are loaded from it.
+    pub(super) fn new(
+        config: &PvOutputConfig,
+        backoff: &BackoffConfig,
+    ) -> color_eyre::Result<Self> {
+        let timezone = timezones::get_by_name(&config.timezone)
+            .ok_or_else(|| eyre!("unknown time zone: {}", config.timezone))?;
+        let client = Client::builder().build()?;
+        let queue = match config.queue_path.as_deref().map(load_queue) {
+            Some(Ok(queue)) => queue,
+            Some(Err(e)) => {
+                warn!(error = %e, "Failed to load PVOutput queue");
+                VecDeque::new()
+            }
+            None => VecDeque::new(),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            timezone,
+            client,
+            queue,
+            last_uploaded: 0,
+            requests: VecDeque::new(),
+            retry_at: 0,
+            backoff: Backoff::new(backoff),
+        })
+    }
+
+    /// Uploads the statuses of the configured site whenever they are updated.
+    ///
+    /// Derived statuses, e.g. because it is dark, and statuses restored after a restart are not
+    /// uploaded.
+    #[instrument(skip_all, fields(system_id = %self.config.system_id))]
+    pub(super) async fn run(mut self) {
+        let site = match site(self.config.site_id.as_deref()) {
+            Some(site) => site,
+            None => {
+                warn!("Unknown site to upload to PVOutput");
+                return;
+            }
+        };
+        let mut receiver = site.status.subscribe();
+        info!(site_id = %site.id, queued = self.queue.len(), "Uploading statuses to PVOutput");
+        let status = receiver.borrow_and_update().clone();
+        if let Some(status) = status {
+            self.enqueue(&status);
+        }
+
+        loop {
+            self.upload().await;
+
+            let next_attempt_in = self
+                .next_attempt_at()
+                .map(|timestamp| Duration::from_secs(timestamp.saturating_sub(unix_now())));
+            select! {
+                result = receiver.changed() => {
+                    if result.is_err() {
+                        return;
+                    }
+                    let status = receiver.borrow_and_update().clone();
+                    if let Some(status) = status {
+                        self.enqueue(&status);
+                    }
+                }
+                _ = sleep(next_attempt_in.unwrap_or_default()), if next_attempt_in.is_some() => {}
+            }
+        }
+    }
+
+    /// Queues a sample of the given status, if it was retrieved and is not stale.
+    ///
+    /// Only the last sample of each status interval is kept, and intervals that have already been
+    /// uploaded are skipped. Samples that are too old to be uploaded are dropped.
+    fn enqueue(&mut self, status: &Status) {
+        if status.source.is_none() || status.stale {
+            return;
+        }
+
+        let interval_secs = self.config.status_interval.max(1) * 60;
+        let sample = Sample {
+            timestamp: status.last_updated - status.last_updated % interval_secs,
+            energy_wh: u64::from(status.total_kwh) * 1000,
+            power_w: status.current_w,
+        };
+        if sample.timestamp <= self.last_uploaded {
+            return;
+        }
+        match self.queue.back_mut() {
+            Some(last_sample) if last_sample.timestamp == sample.timestamp => *last_sample = sample,
+            Some(last_sample) if last_sample.timestamp > sample.timestamp => return,
+            _ => self.queue.push_back(sample),
+        }
+        debug!(?sample, "Queued sample");
+
+        let oldest_timestamp = unix_now().saturating_sub(self.config.max_backfill_days * 86400);
+        while let Some(sample) = self.queue.front() {
+            if sample.timestamp >= oldest_timestamp {
+                break;
+            }
+            warn!(?sample, "Dropped sample that is too old to upload");
+            self.queue.pop_front();
+        }
+        self.save_queue();
+    }
+
+    /// Saves the queue to its file, if enabled.
+    fn save_queue(&self) {
+        if let Some(queue_path) = &self.config.queue_path {
+            if let Err(e) = write_queue(queue_path, &self.queue) {
+                warn!(error = %e, "Failed to save PVOutput queue");
+            }
+        }
+    }
+
+    /// Returns the (UNIX) timestamp at which the rate limit allows another request.
+    fn rate_limit_reset_at(&mut self) -> u64 {
+        let timestamp = unix_now();
+        while let Some(request_timestamp) = self.requests.front() {
+            if timestamp < request_timestamp + RATE_LIMIT_WINDOW_SECS {
+                break;
+            }
+            self.requests.pop_front();
+        }
+
+        if self.requests.len() < self.config.max_requests_per_hour {
+            timestamp
+        } else {
+            self.requests
+                .front()
+                .map_or(timestamp, |request_timestamp| {
+                    request_timestamp + RATE_LIMIT_WINDOW_SECS
+                })
+        }
+    }
+
+    /// Returns the (UNIX) timestamp of the next upload attempt, if there are queued samples.
+    fn next_attempt_at(&mut self) -> Option<u64> {
+        if self.queue.is_empty() {
+            return None;
+        }
+
+        Some(self.retry_at.max(self.rate_limit_reset_at()))
+    }
+
+    /// Uploads the queued samples, as long as no failure occurs and the rate limit allows it.
+    ///
+    /// A single sample is uploaded as a status, multiple samples are uploaded in batches.
+    async fn upload(&mut self) {
+        while let Some(next_attempt_at) = self.next_attempt_at() {
+            let timestamp = unix_now();
+            if next_attempt_at > timestamp {
+                debug!(
+                    queued = self.queue.len(),
+                    retry_in_secs = next_attempt_at - timestamp,
+                    "Postponed uploading samples"
+                );
+                return;
+            }
+
+            let count = self.queue.len().min(MAX_BATCH_SIZE);
+            let samples = self.queue.iter().take(count).copied().collect::<Vec<_>>();
+            self.requests.push_back(timestamp);
+            let result = if let [sample] = samples.as_slice() {
+                self.add_status(sample).await
+            } else {
+                self.add_batch_status(&samples).await
+            };
+
+            match result {
+                Ok(rate_limit_reset_at) => {
+                    info!(count, "Uploaded samples to PVOutput");
+                    self.queue.drain(..count);
+                    self.last_uploaded = samples.last().map_or(0, |sample| sample.timestamp);
+                    self.backoff.reset();
+                    self.retry_at = rate_limit_reset_at.unwrap_or_default();
+                }
+                Err(UploadError::Rejected(message)) => {
+                    warn!(count, %message, "PVOutput rejected samples, dropping them");
+                    self.queue.drain(..count);
+                }
+                Err(UploadError::RateLimited(reset_at)) => {
+                    self.retry_at = reset_at.unwrap_or(timestamp + RATE_LIMIT_WINDOW_SECS);
+                    warn!(
+                        retry_in_secs = self.retry_at.saturating_sub(timestamp),
+                        "PVOutput rate limit exceeded"
+                    );
+                }
+                Err(e) => {
+                    let delay = self.backoff.next_delay();
+                    self.retry_at = timestamp + delay.as_secs();
+                    warn!(
+                        error = %e,
+                        retry_in_secs = delay.as_secs(),
+                        "Failed to upload samples to PVOutput"
+                    );
+                }
+            }
+            self.save_queue();
+        }
+    }
+
+    /// Returns the local date and time of the system at the given (UNIX) timestamp in the format
+    /// used by PVOutput.
+    fn local_date_time(&self, timestamp: u64) -> (String, String) {
+        let date_time = OffsetDateTime::from_unix_timestamp(timestamp as i64)
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+            .to_timezone(self.timezone);
+
+        (
+            format!(
+                "{:04}{:02}{:02}",
+                date_time.year(),
+                u8::from(date_time.month()),
+                date_time.day()
+            ),
+            format!("{:02}:{:02}", date_time.hour(), date_time.minute()),
+        )
+    }
+
+    /// Builds a request to the given PVOutput service.
+    fn request(&self, service: &str) -> RequestBuilder {
+        self.client
+            .post(format!("{}/service/r2/{}", self.config.base_url, service))
+            .header("X-Pvoutput-Apikey", self.config.api_key.expose())
+            .header("X-Pvoutput-SystemId", &self.config.system_id)
+            .header("X-Rate-Limit", "1")
+    }
+
+    /// Sends the given request and returns the response body, and the (UNIX) timestamp at which
+    /// the rate limit resets if it has been reached.
+    async fn send(&self, request: RequestBuilder) -> Result<(String, Option<u64>), UploadError> {
+        let response = request.send().await?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let rate_limit_reset_at = header("X-Rate-Limit-Reset");
+        let rate_limit_reached = header("X-Rate-Limit-Remaining") == Some(0);
+        let http_status = response.status();
+        let body = response.text().await?;
+
+        match http_status {
+            status if status.is_success() => {
+                Ok((body, rate_limit_reset_at.filter(|_| rate_limit_reached)))
+            }
+            StatusCode::BAD_REQUEST => Err(UploadError::Rejected(body)),
+            StatusCode::FORBIDDEN if body.contains("Exceeded") => {
+                Err(UploadError::RateLimited(rate_limit_reset_at))
+            }
+            status => Err(UploadError::HttpStatus(status, body)),
+        }
+    }
+
+    /// Uploads the given sample as a status.
+    ///
+    /// The energy is uploaded as a lifetime value, so that PVOutput derives the energy produced
+    /// per day from it.
+    async fn add_status(&self, sample: &Sample) -> Result<Option<u64>, UploadError> {
+        let (date, time) = self.local_date_time(sample.timestamp);
+        let request = self.request("addstatus.jsp").form(&[
+            ("d", date),
+            ("t", time),
+            ("v1", sample.energy_wh.to_string()),
+            ("v2", sample.power_w.to_string()),
+            ("c1", String::from("2")),
+        ]);
+        let (_, rate_limit_reset_at) = self.send(request).await?;
+
+        Ok(rate_limit_reset_at)
+    }
+
+    /// Uploads the given samples as a batch of statuses.
+    ///
+    /// Samples that PVOutput did not add, e.g. because they are duplicates, are not retried.
+    async fn add_batch_status(&self, samples: &[Sample]) -> Result<Option<u64>, UploadError> {
+        let data = samples
+            .iter()
+            .map(|sample| {
+                let (date, time) = self.local_date_time(sample.timestamp);
+                format!("{},{},{},{}", date, time, sample.energy_wh, sample.power_w)
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        let request = self
+            .request("addbatchstatus.jsp")
+            .form(&[("data", data), ("c1", String::from("2"))]);
+        let (body, rate_limit_reset_at) = self.send(request).await?;
+        let not_added = body
+            .split(';')
+            .filter(|entry| entry.ends_with(",0"))
+            .count();
+        if not_added > 0 {
+            warn!(not_added, "PVOutput did not add some samples");
+        }
+
+        Ok(rate_limit_reset_at)
+    }
+}
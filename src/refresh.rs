@@ -0,0 +1,124 @@
+//! Module for refreshing the status of a site on demand, outside the regular schedule.
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use rocket::http::{ContentType, Status as HttpStatus};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::oneshot;
+use rocket::tokio::time::timeout;
+use thiserror::Error;
+use tracing::debug;
+
+use super::auth::Authenticated;
+use super::{site, Status, UPDATERS};
+
+/// The maximum time to wait for a refresh to complete.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A request to the update loop of a site to refresh its status.
+#[derive(Debug)]
+pub(super) struct RefreshRequest {
+    /// The Autarco site ID
+    pub(super) site_id: String,
+    /// The sender to reply to the request with
+    pub(super) reply: oneshot::Sender<Result<Status, RefreshError>>,
+}
+
+/// The errors that can occur when refreshing the status of a site.
+#[derive(Clone, Copy, Debug, Error)]
+pub(super) enum RefreshError {
+    /// The site is unknown
+    #[error("Unknown site")]
+    UnknownSite,
+    /// The update loop of the site is not available or did not respond in time
+    #[error("Updater not available")]
+    Unavailable,
+    /// The last poll failed and was too recent to poll again already
+    #[error("Refreshed too recently, retry in {retry_after_secs} seconds")]
+    TooSoon {
+        /// The time after which a refresh is allowed again (s)
+        retry_after_secs: u64,
+    },
+    /// Updating the status failed
+    #[error("Failed to update the status")]
+    Failed,
+}
+
+impl<'r> Responder<'r, 'static> for RefreshError {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let http_status = match self {
+            RefreshError::UnknownSite => HttpStatus::NotFound,
+            RefreshError::Unavailable => HttpStatus::ServiceUnavailable,
+            RefreshError::TooSoon { .. } => HttpStatus::TooManyRequests,
+            RefreshError::Failed => HttpStatus::BadGateway,
+        };
+        let body = serde_json::json!({ "error": self.to_string() }).to_string();
+        let mut response = Response::build();
+        response
+            .status(http_status)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body));
+        if let RefreshError::TooSoon { retry_after_secs } = self {
+            response.raw_header("Retry-After", retry_after_secs.to_string());
+        }
+
+        Ok(response.finalize())
+    }
+}
+
+/// Request guard that only succeeds if the request is authenticated with an API token.
+///
+/// If API authentication is not configured, refreshing is disabled and the request is forbidden.
+#[derive(Debug)]
+pub(super) struct RefreshAuthorized;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RefreshAuthorized {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let authenticated = match req.guard::<Authenticated>().await {
+            request::Outcome::Success(authenticated) => authenticated,
+            request::Outcome::Failure((http_status, ())) => {
+                return request::Outcome::Failure((http_status, ()))
+            }
+            request::Outcome::Forward(()) => return request::Outcome::Forward(()),
+        };
+        match authenticated.token_name() {
+            Some(token_name) => {
+                debug!(token_name, "Authorized refresh request");
+                request::Outcome::Success(RefreshAuthorized)
+            }
+            None => request::Outcome::Failure((HttpStatus::Forbidden, ())),
+        }
+    }
+}
+
+/// Refreshes the status of the given site, or the default site if no site ID is given.
+///
+/// The refresh is performed by the update loop of the site, which coalesces concurrent requests
+/// and only polls if the last poll is at least the configured minimum refresh interval ago.
+pub(super) async fn refresh(site_id: Option<&str>) -> Result<Json<Status>, RefreshError> {
+    let site = site(site_id).ok_or(RefreshError::UnknownSite)?;
+    let updater = UPDATERS
+        .get()
+        .and_then(|updaters| {
+            updaters
+                .iter()
+                .find(|updater| updater.site_ids().contains(&site.id))
+        })
+        .ok_or(RefreshError::Unavailable)?;
+
+    let (reply, reply_receiver) = oneshot::channel();
+    updater.request_refresh(RefreshRequest {
+        site_id: site.id.clone(),
+        reply,
+    })?;
+    match timeout(REFRESH_TIMEOUT, reply_receiver).await {
+        Ok(Ok(result)) => result.map(Json),
+        Ok(Err(_)) | Err(_) => Err(RefreshError::Unavailable),
+    }
+}
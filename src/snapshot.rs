@@ -0,0 +1,59 @@
+//! Module for persisting the last known statuses so that they survive restarts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::atomic_file;
+use super::{Status, SITES};
+
+/// The state file that snapshots of the last known statuses of all sites are written to.
+#[derive(Debug)]
+pub(super) struct Snapshot {
+    /// The path of the state file
+    path: PathBuf,
+    /// Lock to prevent concurrent writes to the state file
+    write_lock: Mutex<()>,
+}
+
+impl Snapshot {
+    /// Creates a snapshot using the state file at the given path.
+    pub(super) fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Loads the last known statuses by site ID from the state file.
+    ///
+    /// If the state file does not exist yet, no statuses are returned.
+    pub(super) fn load(&self) -> io::Result<HashMap<String, Status>> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves the current statuses of all tracked sites to the state file.
+    ///
+    /// The state file is replaced atomically, so that it is never left partially written.
+    pub(super) fn save(&self) -> io::Result<()> {
+        let statuses = SITES
+            .get()
+            .map(|sites| {
+                sites
+                    .iter()
+                    .filter_map(|site| Some((site.id.clone(), site.status.borrow().clone()?)))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let contents = serde_json::to_vec(&statuses)?;
+
+        let _write_guard = self.write_lock.lock().expect("Write lock was poisoned");
+        atomic_file::write(&self.path, &contents)
+    }
+}
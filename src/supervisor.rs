@@ -0,0 +1,252 @@
+//! Module for supervising the update loops and backing off on failures.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use rocket::tokio::{self, time::sleep};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info_span, warn, Instrument};
+
+use super::mqtt::MqttPublisher;
+use super::update::{update_loop, UpdaterState};
+use super::{unix_now, AccountConfig, Config};
+
+/// The configuration of the backoff on failures.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(super) struct BackoffConfig {
+    /// The initial delay after a failure (s)
+    initial_secs: u64,
+    /// The maximum delay after repeated failures (s)
+    max_secs: u64,
+    /// The number of consecutive rejected logins after which the circuit breaker opens (if any)
+    auth_failure_threshold: Option<u32>,
+    /// The time the circuit breaker stays open before a login is tried again (s)
+    circuit_open_secs: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_secs: 10,
+            max_secs: 3600,
+            auth_failure_threshold: None,
+            circuit_open_secs: 3600,
+        }
+    }
+}
+
+/// Exponential backoff with jitter.
+#[derive(Debug)]
+pub(super) struct Backoff {
+    /// The initial delay (s)
+    initial_secs: u64,
+    /// The maximum delay (s)
+    max_secs: u64,
+    /// The number of consecutive failures
+    failures: u32,
+}
+
+impl Backoff {
+    /// Creates a new backoff using the given configuration.
+    pub(super) fn new(config: &BackoffConfig) -> Self {
+        Self {
+            initial_secs: config.initial_secs,
+            max_secs: config.max_secs,
+            failures: 0,
+        }
+    }
+
+    /// Registers a failure and returns the delay before the next attempt.
+    ///
+    /// The delay doubles with each consecutive failure up to the maximum delay. A random jitter
+    /// of up to half the delay is subtracted to avoid synchronized retries.
+    pub(super) fn next_delay(&mut self) -> Duration {
+        let delay_secs = 2u64
+            .saturating_pow(self.failures)
+            .saturating_mul(self.initial_secs)
+            .min(self.max_secs);
+        self.failures = self.failures.saturating_add(1);
+        let jitter_secs = rand::thread_rng().gen_range(0..=delay_secs / 2);
+
+        Duration::from_secs(delay_secs - jitter_secs)
+    }
+
+    /// Resets the backoff after a success.
+    pub(super) fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// The backoff and circuit breaker of an update loop or of a single provider.
+///
+/// After a failure, the next attempt is delayed using exponential backoff. If configured, after a
+/// number of consecutive rejected logins the circuit breaker opens and no attempts are made for a
+/// while to prevent the account from getting locked. After the circuit breaker closes again, a
+/// single attempt is allowed (half-open state); if its login is rejected as well, the circuit
+/// breaker opens again immediately.
+#[derive(Debug)]
+pub(super) struct Breaker {
+    /// The backoff after failures
+    backoff: Backoff,
+    /// The number of consecutive rejected logins after which the circuit breaker opens (if any)
+    auth_failure_threshold: Option<u32>,
+    /// The time the circuit breaker stays open before a login is tried again (s)
+    circuit_open_secs: u64,
+    /// The number of consecutive rejected logins
+    auth_failures: u32,
+    /// The (UNIX) timestamp before which no attempt is made again
+    retry_at: u64,
+}
+
+impl Breaker {
+    /// Creates a new (closed) breaker using the given configuration.
+    pub(super) fn new(config: &BackoffConfig) -> Self {
+        Self {
+            backoff: Backoff::new(config),
+            auth_failure_threshold: config.auth_failure_threshold,
+            circuit_open_secs: config.circuit_open_secs,
+            auth_failures: 0,
+            retry_at: 0,
+        }
+    }
+
+    /// Returns whether an attempt can be made at the given (UNIX) timestamp.
+    pub(super) fn is_available(&self, timestamp: u64) -> bool {
+        timestamp >= self.retry_at
+    }
+
+    /// Returns the number of consecutive rejected logins.
+    pub(super) fn auth_failures(&self) -> u32 {
+        self.auth_failures
+    }
+
+    /// Registers a failure at the given (UNIX) timestamp, which is a rejected login or not, and
+    /// returns the resulting phase and the delay before the next attempt.
+    ///
+    /// If the login was rejected repeatedly, the circuit breaker opens; otherwise it backs off.
+    pub(super) fn record_failure(&mut self, rejected: bool, timestamp: u64) -> (Phase, Duration) {
+        if rejected {
+            self.auth_failures = self.auth_failures.saturating_add(1);
+        } else {
+            self.auth_failures = 0;
+        }
+
+        let circuit_open = self
+            .auth_failure_threshold
+            .is_some_and(|threshold| self.auth_failures >= threshold);
+        let (phase, delay) = if circuit_open {
+            warn!(
+                auth_failures = self.auth_failures,
+                retry_in_secs = self.circuit_open_secs,
+                "Login rejected repeatedly, opening circuit breaker"
+            );
+            (
+                Phase::CircuitOpen,
+                Duration::from_secs(self.circuit_open_secs),
+            )
+        } else {
+            (Phase::BackingOff, self.backoff.next_delay())
+        };
+        self.retry_at = timestamp + delay.as_secs();
+
+        (phase, delay)
+    }
+
+    /// Resets the backoff, but not the number of consecutive rejected logins, after recovering.
+    pub(super) fn reset_backoff(&mut self) {
+        self.backoff.reset();
+    }
+
+    /// Resets the breaker after a success.
+    pub(super) fn reset(&mut self) {
+        self.backoff.reset();
+        self.auth_failures = 0;
+        self.retry_at = 0;
+    }
+}
+
+/// The phase an update loop is in, as seen by its supervisor.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Phase {
+    /// The update loop is (re)starting or running
+    #[default]
+    Running,
+    /// The update loop failed and is restarted after a delay
+    BackingOff,
+    /// Logins were rejected repeatedly and no attempts are made until the circuit breaker closes
+    CircuitOpen,
+}
+
+/// The state of the supervisor of an update loop.
+#[derive(Debug, Default, Serialize)]
+pub(super) struct SupervisorState {
+    /// The phase the update loop is in
+    pub(super) phase: Phase,
+    /// The number of times the update loop has been restarted
+    pub(super) restarts: u32,
+    /// The number of consecutive rejected logins
+    pub(super) auth_failures: u32,
+    /// The (UNIX) timestamp of the next restart attempt when backing off or the circuit is open
+    pub(super) retry_at: Option<u64>,
+}
+
+/// Supervises the update loop of the given account.
+///
+/// It (re)starts the update loop and restarts it with exponential backoff when it fails, e.g.
+/// because logging in failed, or when it panics. If configured, after a number of consecutive
+/// rejected logins the circuit breaker opens and no attempts are made for a while to prevent the
+/// account from getting locked.
+pub(super) async fn supervise(
+    config: Arc<Config>,
+    account: AccountConfig,
+    mqtt_publisher: Option<Arc<MqttPublisher>>,
+    state: Arc<UpdaterState>,
+) {
+    let _alive_guard = state.alive_guard();
+    let mut breaker = Breaker::new(&config.backoff);
+
+    loop {
+        state.update_supervisor(|supervisor| {
+            supervisor.phase = Phase::Running;
+            supervisor.retry_at = None;
+        });
+
+        let start = Instant::now();
+        let span = info_span!("update_loop", site_ids = ?account.site_ids);
+        let handle = tokio::spawn(
+            update_loop(
+                Arc::clone(&config),
+                account.clone(),
+                mqtt_publisher.clone(),
+                Arc::clone(&state),
+            )
+            .instrument(span),
+        );
+        match handle.await {
+            Ok(Ok(())) => error!("Update loop stopped unexpectedly"),
+            Ok(Err(e)) => error!(error = %e, "Update loop failed"),
+            Err(e) => error!(error = %e, "Update loop panicked"),
+        }
+
+        // Consider the loop to have recovered if it ran for at least a poll interval.
+        if start.elapsed() >= Duration::from_secs(config.poll_interval) {
+            breaker.reset_backoff();
+        }
+
+        let timestamp = unix_now();
+        let (phase, delay) = breaker.record_failure(state.is_login_rejected(), timestamp);
+        if phase == Phase::BackingOff {
+            warn!(retry_in_secs = delay.as_secs(), "Restarting update loop");
+        }
+        state.update_supervisor(|supervisor| {
+            supervisor.phase = phase;
+            supervisor.restarts += 1;
+            supervisor.auth_failures = breaker.auth_failures();
+            supervisor.retry_at = Some(timestamp + delay.as_secs());
+        });
+        sleep(delay).await;
+    }
+}
@@ -1,131 +1,691 @@
 //! Module for handling the status updating/retrieval via the My Autarco site/API.
 
-use std::time::{Duration, SystemTime};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use reqwest::{Client, ClientBuilder, Error, StatusCode};
+use autarco_scraper::{AutarcoClient, LoginError, Observer, UpdateError};
+use color_eyre::eyre::bail;
+use reqwest_cookie_store::CookieStoreMutex;
+use rocket::tokio::select;
+use rocket::tokio::sync::{mpsc, Mutex as AsyncMutex};
 use rocket::tokio::time::sleep;
-use serde::Deserialize;
-use url::{ParseError, Url};
+use serde::Serialize;
+use tracing::{debug, info, info_span, instrument, warn};
 
-use super::{Config, Status, BASE_URL, POLL_INTERVAL, STATUS};
+use super::cookies;
+use super::daylight::DaylightConfig;
+use super::error_log::ERROR_LOG;
+use super::json_provider::JsonProvider;
+use super::metrics::METRICS;
+use super::modbus_provider::ModbusProvider;
+use super::mqtt::MqttPublisher;
+use super::provider::{CrossCheckConfig, ProviderError, ProviderKind, SolarProvider};
+use super::refresh::{RefreshError, RefreshRequest};
+use super::supervisor::{Backoff, Breaker, Phase, SupervisorState};
+use super::{site, unix_now, AccountConfig, Config, Status, HISTORY, SNAPSHOT};
 
-/// Returns the login URL for the My Autarco site.
-fn login_url() -> Result<Url, ParseError> {
-    Url::parse(&format!("{}/auth/login", BASE_URL))
+/// The maximum number of pending refresh requests of an update loop.
+const MAX_PENDING_REFRESH_REQUESTS: usize = 32;
+
+/// The state of a supervised update loop, used to report its health.
+#[derive(Debug, Serialize)]
+pub(super) struct UpdaterState {
+    /// The Autarco site IDs that are updated
+    site_ids: Vec<String>,
+    /// Whether the supervisor of the update loop is running
+    alive: AtomicBool,
+    /// Whether the last login succeeded
+    logged_in: AtomicBool,
+    /// The failure of the last login (if it failed)
+    login_failure: Mutex<Option<LoginFailure>>,
+    /// The state of the supervisor of the update loop
+    supervisor: Mutex<SupervisorState>,
+    /// The sender of refresh requests to the update loop
+    #[serde(skip)]
+    refresh_requests: mpsc::Sender<RefreshRequest>,
+    /// The receiver of refresh requests, owned by the running update loop
+    #[serde(skip)]
+    refresh_receiver: AsyncMutex<mpsc::Receiver<RefreshRequest>>,
+}
+
+impl UpdaterState {
+    /// Creates the state of an update loop that has not started yet for the given site IDs.
+    pub(super) fn new(site_ids: Vec<String>) -> Self {
+        let (refresh_requests, refresh_receiver) = mpsc::channel(MAX_PENDING_REFRESH_REQUESTS);
+
+        Self {
+            site_ids,
+            alive: AtomicBool::new(false),
+            logged_in: AtomicBool::new(false),
+            login_failure: Mutex::new(None),
+            supervisor: Mutex::new(SupervisorState::default()),
+            refresh_requests,
+            refresh_receiver: AsyncMutex::new(refresh_receiver),
+        }
+    }
+
+    /// Returns the Autarco site IDs that are updated.
+    pub(super) fn site_ids(&self) -> &[String] {
+        &self.site_ids
+    }
+
+    /// Requests the update loop to refresh the status of a site.
+    ///
+    /// Fails if too many refresh requests are pending already.
+    pub(super) fn request_refresh(&self, request: RefreshRequest) -> Result<(), RefreshError> {
+        self.refresh_requests
+            .try_send(request)
+            .map_err(|_| RefreshError::Unavailable)
+    }
+
+    /// Returns whether the supervisor of the update loop is running.
+    pub(super) fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    /// Marks the supervisor of the update loop as alive as long as the returned guard exists.
+    pub(super) fn alive_guard(&self) -> AliveGuard<'_> {
+        AliveGuard::new(self)
+    }
+
+    /// Returns whether the last login of the update loop succeeded.
+    pub(super) fn is_logged_in(&self) -> bool {
+        self.logged_in.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the last login of the update loop was rejected by the My Autarco site.
+    pub(super) fn is_login_rejected(&self) -> bool {
+        self.login_failure
+            .lock()
+            .expect("Login failure mutex was poisoned")
+            .as_ref()
+            .is_some_and(|failure| failure.rejected)
+    }
+
+    /// Records the result of a login.
+    fn record_login(&self, result: &Result<(), LoginError>) {
+        self.logged_in.store(result.is_ok(), Ordering::Relaxed);
+        let mut login_failure = self
+            .login_failure
+            .lock()
+            .expect("Login failure mutex was poisoned");
+        *login_failure = result.as_ref().err().map(LoginFailure::from);
+    }
+
+    /// Returns whether the update loop is running according to its supervisor.
+    pub(super) fn is_running(&self) -> bool {
+        self.update_supervisor(|supervisor| supervisor.phase == Phase::Running)
+    }
+
+    /// Updates the state of the supervisor using the given function and returns its result.
+    pub(super) fn update_supervisor<T>(&self, f: impl FnOnce(&mut SupervisorState) -> T) -> T {
+        let mut supervisor = self
+            .supervisor
+            .lock()
+            .expect("Supervisor state mutex was poisoned");
+        f(&mut supervisor)
+    }
 }
 
-/// Returns an API endpoint URL for the given site ID and endpoint of the My Autarco site.
-fn api_url(site_id: &str, endpoint: &str) -> Result<Url, ParseError> {
-    Url::parse(&format!(
-        "{}/api/site/{}/kpis/{}",
-        BASE_URL, site_id, endpoint
-    ))
+/// Guard that marks a supervisor of an update loop as alive as long as it exists.
+///
+/// Because the guard is dropped when the supervisor ends or panics, it is marked as not alive
+/// anymore in all these cases.
+#[derive(Debug)]
+pub(super) struct AliveGuard<'a>(&'a UpdaterState);
+
+impl<'a> AliveGuard<'a> {
+    /// Marks the supervisor of the update loop with the given state as alive.
+    fn new(state: &'a UpdaterState) -> Self {
+        state.alive.store(true, Ordering::Relaxed);
+
+        Self(state)
+    }
 }
 
-/// The energy data returned by the energy API endpoint.
-#[derive(Debug, Deserialize)]
-struct ApiEnergy {
-    /// Total energy produced today (kWh)
-    // pv_today: u32,
-    /// Total energy produced this month (kWh)
-    // pv_month: u32,
-    /// Total energy produced since installation (kWh)
-    pv_to_date: u32,
+impl Drop for AliveGuard<'_> {
+    fn drop(&mut self) {
+        self.0.alive.store(false, Ordering::Relaxed);
+    }
 }
 
-///  The power data returned by the power API endpoint.
-#[derive(Debug, Deserialize)]
-struct ApiPower {
-    /// Current power production (W)
-    pv_now: u32,
+/// The failure of a login, as reported by the API.
+#[derive(Clone, Debug, Serialize)]
+struct LoginFailure {
+    /// The kind of login error
+    kind: &'static str,
+    /// The description of the login error
+    message: String,
+    /// Whether the login was rejected by the My Autarco site
+    rejected: bool,
 }
 
-/// Performs a login on the My Autarco site.
-///
-/// It mainly stores the acquired cookie in the client's cookie jar. The login credentials come
-/// from the loaded configuration (see [`Config`]).
-async fn login(config: &Config, client: &Client) -> Result<(), Error> {
-    let params = [
-        ("username", &config.username),
-        ("password", &config.password),
-    ];
-    let login_url = login_url().expect("valid login URL");
+impl From<&LoginError> for LoginFailure {
+    fn from(error: &LoginError) -> Self {
+        Self {
+            kind: error.kind(),
+            message: error.to_string(),
+            rejected: error.is_rejected(),
+        }
+    }
+}
+
+/// The update schedule of a site.
+#[derive(Debug)]
+struct Schedule {
+    /// The (UNIX) timestamp of the last successful update
+    last_updated: u64,
+    /// The (UNIX) timestamp of the last update attempt
+    last_polled: u64,
+    /// The (UNIX) timestamp before which no update is attempted after a failure
+    retry_at: u64,
+    /// The backoff for failed updates
+    backoff: Backoff,
+}
+
+/// Observer of the client of a session that records its logins and requests.
+#[derive(Debug)]
+struct SessionObserver {
+    /// The state of the update loop that the session belongs to
+    state: Arc<UpdaterState>,
+    /// The cookie jar of the client
+    cookie_jar: Arc<CookieStoreMutex>,
+    /// The path of the file to persist the cookie jar in (if enabled)
+    cookie_jar_path: Option<PathBuf>,
+}
+
+impl SessionObserver {
+    /// Saves the cookie jar to its file, if enabled.
+    fn save_cookie_jar(&self) {
+        if let Some(cookie_jar_path) = &self.cookie_jar_path {
+            if let Err(e) = cookies::save(cookie_jar_path, &self.cookie_jar) {
+                warn!(error = %e, "Failed to save cookie jar");
+            }
+        }
+    }
+}
+
+impl Observer for SessionObserver {
+    fn request_completed(&self, endpoint: &str, latency: Duration) {
+        METRICS.observe_latency(endpoint, latency.as_secs_f64());
+    }
+
+    fn logged_in(&self, result: &Result<(), LoginError>) {
+        METRICS.observe_login();
+        self.state.record_login(result);
+        match result {
+            Ok(()) => {
+                info!("Logged in successfully");
+                self.save_cookie_jar();
+            }
+            Err(e) => {
+                warn!(kind = e.kind(), error = %e, "Failed to log in");
+                ERROR_LOG.push(None, e.kind(), e.to_string());
+            }
+        }
+    }
+}
+
+/// A logged in session on the My Autarco site for an account.
+#[derive(Debug)]
+struct Session {
+    /// The configuration
+    config: Arc<Config>,
+    /// The account that is logged in
+    account: AccountConfig,
+    /// The providers to retrieve the statuses from, in the order they are tried
+    providers: Vec<SessionProvider>,
+    /// The observer of the My Autarco client (if used)
+    observer: Arc<SessionObserver>,
+}
+
+/// A provider of a session, with its name and its backoff and circuit breaker.
+#[derive(Debug)]
+struct SessionProvider {
+    /// The name of the provider, recorded as the source of the statuses it retrieves
+    name: String,
+    /// The provider itself
+    provider: Box<dyn SolarProvider>,
+    /// The backoff and circuit breaker of the provider
+    breaker: Mutex<Breaker>,
+}
+
+impl SessionProvider {
+    /// Returns whether the provider can be tried at the given timestamp.
+    fn is_available(&self, timestamp: u64) -> bool {
+        self.breaker
+            .lock()
+            .expect("Provider breaker mutex was poisoned")
+            .is_available(timestamp)
+    }
+
+    /// Registers a failed login at the given timestamp, so that the provider is not tried again
+    /// until it has backed off or its circuit breaker has closed.
+    fn record_login_failure(&self, error: &LoginError, timestamp: u64) {
+        let mut breaker = self
+            .breaker
+            .lock()
+            .expect("Provider breaker mutex was poisoned");
+        let (phase, delay) = info_span!("provider", source = self.name)
+            .in_scope(|| breaker.record_failure(error.is_rejected(), timestamp));
+        if phase == Phase::BackingOff {
+            warn!(
+                source = self.name,
+                retry_in_secs = delay.as_secs(),
+                "Backing off from provider"
+            );
+        }
+    }
+
+    /// Retrieves the current status of the given site, updated at the given (UNIX) timestamp,
+    /// with the name of the provider as its source.
+    async fn fetch_status(&self, site_id: &str, timestamp: u64) -> Result<Status, ProviderError> {
+        let status = self.provider.fetch_status(site_id, timestamp).await?;
+
+        Ok(Status {
+            source: Some(self.name.clone()),
+            ..status
+        })
+    }
+}
 
-    client.post(login_url).form(&params).send().await?;
+impl Session {
+    /// Logs in on all providers.
+    ///
+    /// It only returns an error if logging in fails on all providers, so that the other
+    /// providers can still be used if logging in fails on some of them.
+    async fn login(&self) -> Result<(), LoginError> {
+        let mut failure = None;
+        let mut logged_in = false;
+        for provider in &self.providers {
+            match provider.provider.login().await {
+                Ok(()) => logged_in = true,
+                // The login failure has been recorded by the observer already.
+                Err(e) => {
+                    let timestamp = unix_now();
+                    provider.record_login_failure(&e, timestamp);
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+
+        match failure {
+            None => {
+                self.observer.state.record_login(&Ok(()));
+                Ok(())
+            }
+            Some(e) if !logged_in => Err(e),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Polls the status of the given site and handles the result.
+    ///
+    /// The providers are tried in order until one of them succeeds, skipping providers that are
+    /// backing off or whose circuit breaker is open after failed logins. If a poll succeeds, the
+    /// status is stored and published, and it is cross-checked with the next providers if
+    /// enabled. If the session of a provider has expired, the provider renews it itself. If all
+    /// providers fail, it returns an error if logging in (again) failed, otherwise a retry is
+    /// scheduled with a backoff.
+    #[instrument(skip_all, fields(site_id = %site_id))]
+    async fn poll(
+        &self,
+        site_id: &str,
+        schedule: &mut Schedule,
+        mqtt_publisher: Option<&MqttPublisher>,
+        timestamp: u64,
+    ) -> Result<(), LoginError> {
+        schedule.last_polled = timestamp;
+        let start = Instant::now();
+        let mut login_failure = None;
+        let mut retrieved = None;
+        for (index, provider) in self.providers.iter().enumerate() {
+            let source = provider.name.as_str();
+            if !provider.is_available(timestamp) {
+                debug!(source, "Skipping provider that is backing off");
+                continue;
+            }
+            match provider.fetch_status(site_id, timestamp).await {
+                Ok(status) => {
+                    provider
+                        .breaker
+                        .lock()
+                        .expect("Provider breaker mutex was poisoned")
+                        .reset();
+                    retrieved = Some((index, status));
+                    break;
+                }
+                // The login failure has been recorded by the observer already.
+                Err(ProviderError::Login(e)) => {
+                    provider.record_login_failure(&e, timestamp);
+                    login_failure.get_or_insert(e);
+                }
+                Err(e) => {
+                    METRICS.observe_failure(&e);
+                    ERROR_LOG.push(Some(site_id), e.kind(), e.to_string());
+                    warn!(source, kind = e.kind(), error = %e, "Failed to update status");
+
+                    // Renew the session, so that the next poll can use the provider again.
+                    if let ProviderError::Update(UpdateError::Auth(_)) = e {
+                        if let Err(e) = provider.provider.refresh().await {
+                            provider.record_login_failure(&e, timestamp);
+                            login_failure.get_or_insert(e);
+                        }
+                    }
+                }
+            }
+        }
+        METRICS.observe_poll(start.elapsed().as_secs_f64());
+
+        let (index, status) = match retrieved {
+            Some(retrieved) => retrieved,
+            None => {
+                if let Some(e) = login_failure {
+                    return Err(e);
+                }
+
+                let delay = schedule.backoff.next_delay();
+                schedule.retry_at = timestamp + delay.as_secs();
+                warn!(
+                    retry_in_secs = delay.as_secs(),
+                    "Failed to update status using any provider"
+                );
+                return Ok(());
+            }
+        };
+        schedule.last_updated = timestamp;
+        schedule.backoff.reset();
+        self.observer.save_cookie_jar();
+
+        info!(?status, "Updated status");
+        store_status(site_id, status.clone(), mqtt_publisher).await;
+        if let Some(cross_check) = &self.config.cross_check {
+            self.cross_check(cross_check, site_id, &status, index + 1, timestamp)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Cross-checks the given status of the given site with the statuses retrieved by the
+    /// providers starting at the given index, and reports the values that disagree.
+    async fn cross_check(
+        &self,
+        cross_check: &CrossCheckConfig,
+        site_id: &str,
+        status: &Status,
+        start_index: usize,
+        timestamp: u64,
+    ) {
+        let status_source = status.source.as_deref().unwrap_or_default();
+        for provider in self.providers.iter().skip(start_index) {
+            let source = provider.name.as_str();
+            if !provider.is_available(timestamp) {
+                continue;
+            }
+            let other_status = match provider.fetch_status(site_id, timestamp).await {
+                Ok(other_status) => other_status,
+                Err(e) => {
+                    debug!(source, error = %e, "Failed to cross-check status");
+                    continue;
+                }
+            };
+
+            let disagreements = cross_check.disagreements(status, &other_status);
+            if disagreements.is_empty() {
+                continue;
+            }
+            let message = format!(
+                "{} disagrees with {}: {}",
+                source,
+                status_source,
+                disagreements.join(", ")
+            );
+            warn!(source, %message, "Providers disagree");
+            METRICS.observe_disagreement(site_id, source);
+            ERROR_LOG.push(Some(site_id), "disagreement", message);
+        }
+    }
+
+    /// Handles the given refresh requests of the sites with the given schedules.
+    ///
+    /// All requests for the same site are coalesced into a single poll. If the last poll of a
+    /// site is less than the minimum refresh interval ago, it is not polled again, but the
+    /// requests are answered with the current status (if that poll succeeded). It only returns an
+    /// error if logging in again fails.
+    async fn refresh(
+        &self,
+        mut requests: Vec<RefreshRequest>,
+        schedules: &mut [Schedule],
+        mqtt_publisher: Option<&MqttPublisher>,
+    ) -> Result<(), LoginError> {
+        for (site_id, schedule) in self.account.site_ids.iter().zip(schedules.iter_mut()) {
+            let (site_requests, other_requests) = requests
+                .into_iter()
+                .partition::<Vec<_>, _>(|request| &request.site_id == site_id);
+            requests = other_requests;
+            if site_requests.is_empty() {
+                continue;
+            }
+
+            let timestamp = unix_now();
+            let refresh_allowed_at = schedule.last_polled + self.config.refresh_min_interval;
+            let mut login_result = Ok(());
+            if timestamp >= refresh_allowed_at {
+                info!(site_id = %site_id, "Refreshing status on request");
+                login_result = self
+                    .poll(site_id, schedule, mqtt_publisher, timestamp)
+                    .await;
+            }
+            let last_poll_succeeded = schedule.last_updated >= schedule.last_polled;
+            let result = match site(Some(site_id)).and_then(|site| site.status.borrow().clone()) {
+                Some(status) if last_poll_succeeded => Ok(status),
+                _ if timestamp < refresh_allowed_at => Err(RefreshError::TooSoon {
+                    retry_after_secs: refresh_allowed_at - timestamp,
+                }),
+                _ => Err(RefreshError::Failed),
+            };
+            for request in site_requests {
+                let _ = request.reply.send(result.clone());
+            }
+            login_result?;
+        }
 
-    Ok(())
+        Ok(())
+    }
 }
 
-/// Retrieves a status update from the API of the My Autarco site.
+/// Reports that it is dark for the given site at the location of the given daylight
+/// configuration without polling.
 ///
-/// It needs the cookie from the login to be able to perform the action. It uses both the `energy`
-/// and `power` endpoint to construct the [`Status`] struct.
-async fn update(config: &Config, client: &Client, last_updated: u64) -> Result<Status, Error> {
-    // Retrieve the data from the API endpoints.
-    let api_energy_url = api_url(&config.site_id, "energy").expect("valid API energy URL");
-    let api_response = client.get(api_energy_url).send().await?;
-    let api_energy: ApiEnergy = match api_response.error_for_status() {
-        Ok(res) => res.json().await?,
-        Err(err) => return Err(err),
+/// The status is derived from the last known status, but with no power being produced. The
+/// energy produced today (or this month) is reset if the local date (or month) has changed since.
+/// Returns whether a status could be derived, i.e. whether a last known status is available.
+#[instrument(skip_all, fields(site_id = %site_id))]
+async fn report_darkness(
+    site_id: &str,
+    daylight: &DaylightConfig,
+    schedule: &mut Schedule,
+    mqtt_publisher: Option<&MqttPublisher>,
+    timestamp: u64,
+) -> bool {
+    let last_status = match site(Some(site_id)).and_then(|site| site.status.borrow().clone()) {
+        Some(last_status) => last_status,
+        None => return false,
     };
+    let status = daylight.darkness_status(&last_status, timestamp);
+    schedule.last_updated = timestamp;
 
-    let api_power_url = api_url(&config.site_id, "power").expect("valid API power URL");
-    let api_response = client.get(api_power_url).send().await?;
-    let api_power: ApiPower = match api_response.error_for_status() {
-        Ok(res) => res.json().await?,
-        Err(err) => return Err(err),
-    };
+    debug!(?status, "Derived status because it is dark");
+    store_status(site_id, status, mqtt_publisher).await;
+
+    true
+}
 
-    // Update the status.
-    Ok(Status {
-        current_w: api_power.pv_now,
-        total_kwh: api_energy.pv_to_date,
-        last_updated,
-    })
+/// Stores the new status of the given site and publishes it.
+async fn store_status(site_id: &str, status: Status, mqtt_publisher: Option<&MqttPublisher>) {
+    METRICS.observe_status(site_id, &status);
+    if let Some(history) = HISTORY.get() {
+        if let Err(e) = history.insert(site_id, &status) {
+            warn!(error = %e, "Failed to store status in history");
+        }
+    }
+    if let Some(mqtt_publisher) = mqtt_publisher {
+        if let Err(e) = mqtt_publisher.publish_status(site_id, &status) {
+            warn!(error = %e, "Failed to publish status to MQTT");
+        }
+    }
+    if let Some(site) = site(Some(site_id)) {
+        site.status.send_replace(Some(status));
+    }
+    if let Some(snapshot) = SNAPSHOT.get() {
+        if let Err(e) = snapshot.save() {
+            warn!(error = %e, "Failed to save status snapshot");
+        }
+    }
 }
 
 /// Main update loop that logs in and periodically acquires updates from the API.
 ///
-/// It updates the mutex-guarded current update [`Status`] struct which can be retrieved via
-/// Rocket.
-pub(super) async fn update_loop(config: Config) -> color_eyre::Result<()> {
-    let client = ClientBuilder::new().cookie_store(true).build()?;
+/// It logs in to the My Autarco site using the given account and updates the current [`Status`]
+/// struct of each site of the account, which can be retrieved and subscribed to via Rocket. Its
+/// health is reported via the given updater state.
+///
+/// If a cookie jar directory is configured, the cookie jar of the session is persisted in it. A
+/// persisted session is reused and it only logs in again once the session has expired.
+///
+/// If the daylight-aware schedule is configured, it polls with the night poll interval when it is
+/// dark, or it does not poll at all and reports that no power is produced instead.
+///
+/// The statuses are retrieved using the configured providers: if the primary provider fails, the
+/// fallback providers are tried in order.
+///
+/// If an update fails, it is retried with a backoff using the given configuration. It returns an
+/// error if logging in fails, so that it can be restarted by its supervisor.
+pub(super) async fn update_loop(
+    config: Arc<Config>,
+    account: AccountConfig,
+    mqtt_publisher: Option<Arc<MqttPublisher>>,
+    state: Arc<UpdaterState>,
+) -> color_eyre::Result<()> {
+    let cookie_jar_path = config
+        .cookie_jar_dir
+        .as_deref()
+        .zip(account.username.as_deref())
+        .map(|(dir, username)| cookies::cookie_jar_path(dir, username));
+    let cookie_store = match cookie_jar_path.as_deref().map(cookies::load) {
+        Some(Ok(Some(cookie_store))) => Some(cookie_store),
+        Some(Ok(None)) | None => None,
+        Some(Err(e)) => {
+            warn!(error = %e, "Failed to load cookie jar");
+            None
+        }
+    };
+    let restored = cookie_store.is_some();
+    let cookie_jar = Arc::new(CookieStoreMutex::new(cookie_store.unwrap_or_default()));
+    let observer = Arc::new(SessionObserver {
+        state: Arc::clone(&state),
+        cookie_jar: Arc::clone(&cookie_jar),
+        cookie_jar_path,
+    });
+    let mut providers = Vec::new();
+    for provider_config in config.providers() {
+        let provider: Box<dyn SolarProvider> = match &provider_config.kind {
+            ProviderKind::MyAutarco => {
+                let (Some(username), Some(password)) = (&account.username, &account.password)
+                else {
+                    bail!("The My Autarco provider needs the username and password of the account");
+                };
+                Box::new(
+                    AutarcoClient::with_cookie_jar(
+                        &config.base_url,
+                        username,
+                        password.expose(),
+                        Arc::clone(&cookie_jar),
+                    )?
+                    .with_observer(Arc::clone(&observer) as Arc<dyn Observer>),
+                )
+            }
+            ProviderKind::Json(json_config) => Box::new(JsonProvider::new(json_config)?),
+            ProviderKind::Modbus(modbus_config) => Box::new(ModbusProvider::new(modbus_config)),
+        };
+        providers.push(SessionProvider {
+            name: provider_config.name().to_owned(),
+            provider,
+            breaker: Mutex::new(Breaker::new(&config.backoff)),
+        });
+    }
+    let session = Session {
+        config: Arc::clone(&config),
+        account,
+        providers,
+        observer,
+    };
 
-    // Go to the My Autarco site and login.
-    println!("⚡ Logging in...");
-    login(&config, &client).await?;
-    println!("⚡ Logged in successfully!");
+    // Go to the My Autarco site and login, unless a session was restored.
+    if restored {
+        info!("Restored session, not logging in until it has expired");
+        state.record_login(&Ok(()));
+    } else {
+        info!("Logging in...");
+        session.login().await?;
+    }
 
-    let mut last_updated = 0;
+    let mut schedules = session
+        .account
+        .site_ids
+        .iter()
+        .map(|_| Schedule {
+            last_updated: 0,
+            last_polled: 0,
+            retry_at: 0,
+            backoff: Backoff::new(&config.backoff),
+        })
+        .collect::<Vec<_>>();
+    let mut refresh_receiver = state.refresh_receiver.lock().await;
     loop {
-        // Wake up every 10 seconds and check if an update is due.
-        sleep(Duration::from_secs(10)).await;
-
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        if timestamp - last_updated < POLL_INTERVAL {
-            continue;
-        }
-
-        let status = match update(&config, &client, timestamp).await {
-            Ok(status) => status,
-            Err(e) if e.status() == Some(StatusCode::UNAUTHORIZED) => {
-                println!("✨ Update unauthorized, trying to log in again...");
-                login(&config, &client).await?;
-                println!("⚡ Logged in successfully!");
+        // Wake up periodically and check if an update is due, or handle refresh requests (and
+        // those that arrived in the meantime) as soon as they arrive.
+        select! {
+            _ = sleep(Duration::from_secs(config.wake_interval)) => {}
+            Some(request) = refresh_receiver.recv() => {
+                let mut requests = vec![request];
+                while let Ok(request) = refresh_receiver.try_recv() {
+                    requests.push(request);
+                }
+                session
+                    .refresh(requests, &mut schedules, mqtt_publisher.as_deref())
+                    .await?;
                 continue;
             }
-            Err(e) => {
-                println!("✨ Failed to update status: {}", e);
+        }
+
+        for (site_id, schedule) in session.account.site_ids.iter().zip(schedules.iter_mut()) {
+            let timestamp = unix_now();
+            let night_poll_interval = match &config.daylight {
+                Some(daylight) if daylight.is_dark(timestamp) => Some(daylight.night_poll_interval),
+                _ => None,
+            };
+            let poll_interval = night_poll_interval
+                .flatten()
+                .unwrap_or(config.poll_interval);
+            if timestamp - schedule.last_updated < poll_interval || timestamp < schedule.retry_at {
                 continue;
             }
-        };
-        last_updated = timestamp;
 
-        println!("⚡ Updated status to: {:#?}", status);
-        let mut status_guard = STATUS.lock().expect("Status mutex was poisoned");
-        status_guard.replace(status);
+            // If it is dark and not polling at night, report darkness instead if possible.
+            if let (Some(daylight), Some(None)) = (&config.daylight, night_poll_interval) {
+                let mqtt_publisher = mqtt_publisher.as_deref();
+                if report_darkness(site_id, daylight, schedule, mqtt_publisher, timestamp).await {
+                    continue;
+                }
+            }
+
+            session
+                .poll(site_id, schedule, mqtt_publisher.as_deref(), timestamp)
+                .await?;
+        }
     }
 }
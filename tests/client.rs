@@ -0,0 +1,117 @@
+//! Integration tests of the library client against a fake My Autarco site.
+
+use autarco_scraper::{AutarcoClient, ClientError, Error, LoginError, UpdateError};
+
+use self::mock_autarco::{MockAutarco, MockLoginResponse, MockResponse, MockState};
+
+// The code generated by Rocket for the routes and forms triggers lints outside the crate root,
+// and not all of the fake site is used here.
+#[allow(dead_code, unused_imports, renamed_and_removed_lints)]
+mod mock_autarco;
+
+/// Creates a client for the given fake My Autarco site with the given password.
+fn client(mock: &MockAutarco, password: &str) -> AutarcoClient {
+    let state = mock.state.lock().expect("Mock state mutex was poisoned");
+    AutarcoClient::new(&mock.base_url, &state.username, password).expect("client can be created")
+}
+
+#[rocket::async_test]
+async fn fetches_kpis() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let client = client(&mock, "secret");
+
+    client.login().await.expect("login succeeds");
+    let energy = client.energy("abc123de").await.expect("energy is fetched");
+    let power = client.power("abc123de").await.expect("power is fetched");
+    assert_eq!(energy.pv_today, Some(4));
+    assert_eq!(energy.pv_month, Some(112));
+    assert_eq!(energy.pv_to_date, 6159);
+    assert_eq!(power.pv_now, 23);
+    assert_eq!(mock.logins(), 1);
+}
+
+#[test]
+fn rejects_invalid_base_url() {
+    let error = AutarcoClient::new("my.autarco.com", "foo@domain.tld", "secret")
+        .expect_err("client cannot be created");
+    assert!(matches!(error, ClientError::InvalidBaseUrl(_)));
+    let error = AutarcoClient::new("mailto:foo@domain.tld", "foo@domain.tld", "secret")
+        .expect_err("client cannot be created");
+    assert!(matches!(error, ClientError::UnsupportedBaseUrl(_)));
+}
+
+#[rocket::async_test]
+async fn accepts_base_url_with_trailing_slash() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let base_url = format!("{}/", mock.base_url);
+    let client =
+        AutarcoClient::new(&base_url, "foo@domain.tld", "secret").expect("client can be created");
+
+    client.login().await.expect("login succeeds");
+    let power = client.power("abc123de").await.expect("power is fetched");
+    assert_eq!(power.pv_now, 23);
+}
+
+#[rocket::async_test]
+async fn rejects_invalid_credentials() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let client = client(&mock, "wrong");
+
+    let error = client.login().await.expect_err("login fails");
+    assert!(matches!(error, LoginError::InvalidCredentials));
+    assert!(error.is_rejected());
+}
+
+#[rocket::async_test]
+async fn rejects_redirect_to_login_page() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script_login([
+        MockLoginResponse::Ok,
+        MockLoginResponse::RedirectToLoginPage,
+    ]);
+    let client = client(&mock, "secret");
+
+    client.login().await.expect("login succeeds");
+    // The session cookie of the first login is still in the cookie jar.
+    let error = client.login().await.expect_err("login fails");
+    assert!(matches!(error, LoginError::UnexpectedLoginPage));
+    assert!(error.is_rejected());
+}
+
+#[rocket::async_test]
+async fn rejects_login_without_session_cookie() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script_login([MockLoginResponse::Ok, MockLoginResponse::NoSessionCookie]);
+    let client = client(&mock, "secret");
+
+    client.login().await.expect("login succeeds");
+    // The session cookie of the first login is still in the cookie jar.
+    let error = client.login().await.expect_err("login fails");
+    assert!(matches!(error, LoginError::MissingSessionCookie));
+    assert!(error.is_rejected());
+}
+
+#[rocket::async_test]
+async fn renews_expired_session() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::Unauthorized]);
+    let client = client(&mock, "secret");
+
+    client.login().await.expect("login succeeds");
+    let power = client.power("abc123de").await.expect("power is fetched");
+    assert_eq!(power.pv_now, 23);
+    assert_eq!(mock.logins(), 2);
+}
+
+#[rocket::async_test]
+async fn reports_update_errors() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::ServerError, MockResponse::Malformed]);
+    let client = client(&mock, "secret");
+
+    client.login().await.expect("login succeeds");
+    let error = client.power("abc123de").await.expect_err("fetch fails");
+    assert!(matches!(error, Error::Update(UpdateError::HttpStatus(_))));
+    let error = client.power("abc123de").await.expect_err("fetch fails");
+    assert!(matches!(error, Error::Update(UpdateError::Decode(_))));
+}
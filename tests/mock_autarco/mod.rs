@@ -0,0 +1,361 @@
+//! A fake My Autarco site to test the scraper against.
+//!
+//! It implements the login and the `energy` and `power` KPI API endpoints. Failures of the login
+//! and the API endpoints can be scripted by queueing [`MockLoginResponse`]s and [`MockResponse`]s.
+//! Additional accounts can be configured, each with their own session and sites.
+//!
+//! It also serves the same data as a single JSON document at `/json/<site_id>`, to test the
+//! generic JSON provider.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::form::Form;
+use rocket::http::{ContentType, Cookie, CookieJar, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::Redirect;
+use rocket::tokio::time::sleep;
+use rocket::{get, post, routes, FromForm, Responder, State};
+
+/// The name of the session cookie that is set after logging in.
+const SESSION_COOKIE: &str = "mock_session";
+
+/// A scripted response of an API endpoint of the fake My Autarco site.
+#[derive(Clone, Copy, Debug)]
+pub enum MockResponse {
+    /// Respond with the configured KPI data
+    Ok,
+    /// Respond with 401 Unauthorized and invalidate the session
+    Unauthorized,
+    /// Respond with 500 Internal Server Error
+    ServerError,
+    /// Respond with malformed JSON
+    Malformed,
+}
+
+/// A scripted response of the login of the fake My Autarco site.
+///
+/// The scripted responses only apply to logins with the correct credentials.
+#[derive(Clone, Copy, Debug)]
+pub enum MockLoginResponse {
+    /// Set the session cookie and redirect to the dashboard
+    Ok,
+    /// Redirect back to the login page without setting the session cookie
+    RedirectToLoginPage,
+    /// Respond with 200 OK without setting the session cookie
+    NoSessionCookie,
+}
+
+/// An additional account of the fake My Autarco site.
+#[derive(Clone, Debug)]
+pub struct MockAccount {
+    /// The username that is accepted to login
+    pub username: String,
+    /// The password that is accepted to login
+    pub password: String,
+    /// The site IDs that the KPI API endpoints are served for
+    pub site_ids: Vec<String>,
+}
+
+/// The state of the fake My Autarco site.
+#[derive(Debug)]
+pub struct MockState {
+    /// The username that is accepted to login
+    pub username: String,
+    /// The password that is accepted to login
+    pub password: String,
+    /// The site ID that the KPI API endpoints are served for
+    pub site_id: String,
+    /// The additional accounts that are accepted to login
+    pub accounts: Vec<MockAccount>,
+    /// The current power production (W) that is served
+    pub pv_now: u32,
+    /// The energy produced today (kWh) that is served
+    pub pv_today: u32,
+    /// The energy produced this month (kWh) that is served
+    pub pv_month: u32,
+    /// The energy produced since installation (kWh) that is served
+    pub pv_to_date: u32,
+    /// The scripted responses for the next KPI API requests
+    pub responses: VecDeque<MockResponse>,
+    /// The scripted responses for the next logins
+    pub login_responses: VecDeque<MockLoginResponse>,
+    /// The number of login requests received
+    pub logins: usize,
+    /// The usernames of the successful logins, in the order they were received
+    pub logged_in_usernames: Vec<String>,
+    /// The number of KPI API requests received
+    pub kpi_requests: usize,
+    /// The `Authorization` header of the last JSON document request (if any)
+    pub json_authorization: Option<String>,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            username: String::from("foo@domain.tld"),
+            password: String::from("secret"),
+            site_id: String::from("abc123de"),
+            accounts: Vec::new(),
+            pv_now: 23,
+            pv_today: 4,
+            pv_month: 112,
+            pv_to_date: 6159,
+            responses: VecDeque::new(),
+            login_responses: VecDeque::new(),
+            logins: 0,
+            logged_in_usernames: Vec::new(),
+            kpi_requests: 0,
+            json_authorization: None,
+        }
+    }
+}
+
+impl MockState {
+    /// Returns whether the given credentials are accepted to login.
+    fn accepts(&self, username: &str, password: &str) -> bool {
+        (username == self.username && password == self.password)
+            || self
+                .accounts
+                .iter()
+                .any(|account| username == account.username && password == account.password)
+    }
+
+    /// Returns whether the account with the given username has the given site.
+    fn has_site(&self, username: &str, site_id: &str) -> bool {
+        if username == self.username {
+            return site_id == self.site_id;
+        }
+
+        self.accounts
+            .iter()
+            .filter(|account| account.username == username)
+            .any(|account| account.site_ids.iter().any(|id| id == site_id))
+    }
+}
+
+/// The shared state of the fake My Autarco site.
+type SharedState = Arc<Mutex<MockState>>;
+
+/// A running fake My Autarco site.
+#[derive(Debug)]
+pub struct MockAutarco {
+    /// The base URL the fake site is served at
+    pub base_url: String,
+    /// The state of the fake site
+    pub state: SharedState,
+}
+
+impl MockAutarco {
+    /// Starts a fake My Autarco site with the given state on a free local port.
+    pub async fn start(state: MockState) -> Self {
+        let port = free_port();
+        let state = Arc::new(Mutex::new(state));
+        let figment = rocket::Config::figment()
+            .merge(("address", Ipv4Addr::LOCALHOST))
+            .merge(("port", port))
+            .merge(("log_level", "off"));
+        let rocket = rocket::custom(figment)
+            .mount("/", routes![login, kpi, json])
+            .manage(Arc::clone(&state));
+        rocket::tokio::spawn(rocket.launch());
+        wait_for_port(port).await;
+
+        Self {
+            base_url: format!("http://{}:{}", Ipv4Addr::LOCALHOST, port),
+            state,
+        }
+    }
+
+    /// Queues scripted responses for the next KPI API requests.
+    pub fn script(&self, responses: impl IntoIterator<Item = MockResponse>) {
+        let mut state = self.state.lock().expect("Mock state mutex was poisoned");
+        state.responses.extend(responses);
+    }
+
+    /// Queues scripted responses for the next logins.
+    pub fn script_login(&self, responses: impl IntoIterator<Item = MockLoginResponse>) {
+        let mut state = self.state.lock().expect("Mock state mutex was poisoned");
+        state.login_responses.extend(responses);
+    }
+
+    /// Returns the number of login requests received so far.
+    pub fn logins(&self) -> usize {
+        self.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .logins
+    }
+
+    /// Returns the usernames of the successful logins so far.
+    pub fn logged_in_usernames(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .logged_in_usernames
+            .clone()
+    }
+
+    /// Returns the number of KPI API requests received so far.
+    pub fn kpi_requests(&self) -> usize {
+        self.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .kpi_requests
+    }
+
+    /// Returns the `Authorization` header of the last JSON document request (if any).
+    pub fn json_authorization(&self) -> Option<String> {
+        self.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .json_authorization
+            .clone()
+    }
+}
+
+/// Returns a local port that is currently free.
+pub fn free_port() -> u16 {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("free local port")
+}
+
+/// Waits until something is listening on the given local port.
+pub async fn wait_for_port(port: u16) {
+    while TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err() {
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// The login form.
+#[derive(Debug, FromForm)]
+struct Login<'r> {
+    /// The username
+    username: &'r str,
+    /// The password
+    password: &'r str,
+}
+
+/// The response of the login.
+///
+/// The size of the variants does not matter, since only a few logins are performed per test.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Responder)]
+enum LoginResponse {
+    /// A response with just an HTTP status
+    Status(Status),
+    /// A redirect
+    Redirect(Redirect),
+}
+
+/// Logs in and sets the session cookie, identifying the account, if the credentials are correct.
+#[post("/auth/login", data = "<login>")]
+fn login(
+    login: Form<Login<'_>>,
+    cookies: &CookieJar<'_>,
+    state: &State<SharedState>,
+) -> LoginResponse {
+    let mut state = state.lock().expect("Mock state mutex was poisoned");
+    state.logins += 1;
+    if !state.accepts(login.username, login.password) {
+        return LoginResponse::Status(Status::Unauthorized);
+    }
+
+    match state
+        .login_responses
+        .pop_front()
+        .unwrap_or(MockLoginResponse::Ok)
+    {
+        MockLoginResponse::Ok => {
+            state.logged_in_usernames.push(login.username.to_owned());
+            let cookie = Cookie::build(SESSION_COOKIE, login.username.to_owned()).path("/");
+            cookies.add(cookie.finish());
+            LoginResponse::Redirect(Redirect::to("/dashboard"))
+        }
+        MockLoginResponse::RedirectToLoginPage => {
+            LoginResponse::Redirect(Redirect::to("/auth/login"))
+        }
+        MockLoginResponse::NoSessionCookie => LoginResponse::Status(Status::Ok),
+    }
+}
+
+/// Serves the `energy` and `power` KPI API endpoints for the sites of the logged in account.
+#[get("/api/site/<site_id>/kpis/<endpoint>")]
+fn kpi(
+    site_id: &str,
+    endpoint: &str,
+    cookies: &CookieJar<'_>,
+    state: &State<SharedState>,
+) -> Result<(ContentType, String), Status> {
+    let mut state = state.lock().expect("Mock state mutex was poisoned");
+    state.kpi_requests += 1;
+    let username = match cookies.get(SESSION_COOKIE) {
+        Some(cookie) => cookie.value().to_owned(),
+        None => return Err(Status::Unauthorized),
+    };
+    if !state.has_site(&username, site_id) {
+        return Err(Status::NotFound);
+    }
+
+    match state.responses.pop_front().unwrap_or(MockResponse::Ok) {
+        MockResponse::Ok => {}
+        MockResponse::Unauthorized => {
+            cookies.remove(Cookie::named(SESSION_COOKIE));
+            return Err(Status::Unauthorized);
+        }
+        MockResponse::ServerError => return Err(Status::InternalServerError),
+        MockResponse::Malformed => return Ok((ContentType::JSON, String::from("{\"pv_"))),
+    }
+
+    let body = match endpoint {
+        "energy" => format!(
+            r#"{{"pv_today":{},"pv_month":{},"pv_to_date":{}}}"#,
+            state.pv_today, state.pv_month, state.pv_to_date
+        ),
+        "power" => format!(r#"{{"pv_now":{}}}"#, state.pv_now),
+        _ => return Err(Status::NotFound),
+    };
+
+    Ok((ContentType::JSON, body))
+}
+
+/// Request guard that provides the `Authorization` header of the request (if any).
+#[derive(Debug)]
+struct Authorization(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorization {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let authorization = request.headers().get_one("Authorization").map(String::from);
+
+        request::Outcome::Success(Authorization(authorization))
+    }
+}
+
+/// Serves all KPI data of the configured site as a single JSON document.
+#[get("/json/<site_id>")]
+fn json(
+    site_id: &str,
+    authorization: Authorization,
+    state: &State<SharedState>,
+) -> Result<(ContentType, String), Status> {
+    let mut state = state.lock().expect("Mock state mutex was poisoned");
+    state.json_authorization = authorization.0;
+    if site_id != state.site_id {
+        return Err(Status::NotFound);
+    }
+
+    let body = format!(
+        r#"{{"power":{{"now":{}}},"energy":{{"today":{},"month":{},"total":{}}}}}"#,
+        state.pv_now, state.pv_today, state.pv_month, state.pv_to_date
+    );
+
+    Ok((ContentType::JSON, body))
+}
@@ -0,0 +1,135 @@
+//! A fake Modbus TCP device (simulator) to test the scraper against.
+//!
+//! It serves the holding and input registers it is started with, using the read holding registers
+//! (3) and read input registers (4) functions. Reading a register that is not served results in an
+//! illegal data address exception.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+
+/// The Modbus exception code for an illegal function.
+const ILLEGAL_FUNCTION: u8 = 0x01;
+
+/// The Modbus exception code for an illegal data address.
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+
+/// The registers of the fake Modbus device.
+#[derive(Debug, Default)]
+pub struct MockRegisters {
+    /// The holding registers by address
+    pub holding: HashMap<u16, u16>,
+    /// The input registers by address
+    pub input: HashMap<u16, u16>,
+}
+
+impl MockRegisters {
+    /// Returns the holding registers of a SunSpec three phase inverter at base address 40000 with
+    /// the given power (W) and lifetime energy (Wh).
+    ///
+    /// The power is served with a scale factor of -1 to check that scale factors are applied.
+    pub fn sunspec(power_w: u16, energy_wh: u32) -> Self {
+        let mut words = vec![0x5375, 0x6e53];
+        // The common model, without any meaningful data.
+        words.extend([1, 65]);
+        words.extend([0; 65]);
+        // The three phase inverter model.
+        let mut inverter = [0; 50];
+        inverter[12] = power_w * 10;
+        inverter[13] = (-1i16) as u16;
+        inverter[22] = (energy_wh >> 16) as u16;
+        inverter[23] = energy_wh as u16;
+        words.extend([103, 50]);
+        words.extend(inverter);
+        // The end model.
+        words.extend([0xffff, 0]);
+
+        Self {
+            holding: (40000..).zip(words).collect(),
+            input: HashMap::new(),
+        }
+    }
+}
+
+/// A running fake Modbus device.
+#[derive(Debug)]
+pub struct MockModbus {
+    /// The address (host and port) the fake device is served at
+    pub address: String,
+}
+
+impl MockModbus {
+    /// Starts a fake Modbus device with the given registers on a free local port.
+    pub async fn start(registers: MockRegisters) -> Self {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("free local port");
+        let address = listener
+            .local_addr()
+            .expect("listener has local address")
+            .to_string();
+        let registers = Arc::new(registers);
+        rocket::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                rocket::tokio::spawn(serve(stream, Arc::clone(&registers)));
+            }
+        });
+
+        Self { address }
+    }
+}
+
+/// Serves the Modbus TCP requests on the given connection until it is closed.
+async fn serve(mut stream: TcpStream, registers: Arc<MockRegisters>) -> io::Result<()> {
+    loop {
+        // The MBAP header: transaction ID, protocol ID, length and unit ID.
+        let mut header = [0; 7];
+        if stream.read_exact(&mut header).await.is_err() {
+            return Ok(());
+        }
+        let length = u16::from_be_bytes([header[4], header[5]]);
+        let mut request = vec![0; usize::from(length.saturating_sub(1))];
+        stream.read_exact(&mut request).await?;
+
+        let response = respond(&registers, &request);
+        let mut frame = Vec::with_capacity(header.len() + response.len());
+        frame.extend_from_slice(&header[..4]);
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&response);
+        stream.write_all(&frame).await?;
+    }
+}
+
+/// Returns the response PDU for the given request PDU.
+fn respond(registers: &MockRegisters, request: &[u8]) -> Vec<u8> {
+    let function = request.first().copied().unwrap_or_default();
+    let table = match function {
+        0x03 => &registers.holding,
+        0x04 => &registers.input,
+        _ => return vec![function | 0x80, ILLEGAL_FUNCTION],
+    };
+    if request.len() != 5 {
+        return vec![function | 0x80, ILLEGAL_DATA_ADDRESS];
+    }
+
+    let address = u16::from_be_bytes([request[1], request[2]]);
+    let count = u16::from_be_bytes([request[3], request[4]]);
+    let words = (0..count)
+        .map(|offset| table.get(&address.wrapping_add(offset)).copied())
+        .collect::<Option<Vec<_>>>();
+    match words {
+        Some(words) => {
+            let mut response = vec![function, (words.len() * 2) as u8];
+            for word in words {
+                response.extend_from_slice(&word.to_be_bytes());
+            }
+            response
+        }
+        None => vec![function | 0x80, ILLEGAL_DATA_ADDRESS],
+    }
+}
@@ -0,0 +1,253 @@
+//! A fake MQTT broker to test the scraper against.
+//!
+//! It implements just enough of MQTT 3.1.1 for a publishing client: it accepts any connection,
+//! acknowledges published messages and subscriptions and answers pings. The published messages
+//! and subscriptions are recorded. Messages can be published to the connected clients, and the
+//! clients can be disconnected to simulate a broker restart.
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast;
+
+/// The MQTT control packet type of a CONNECT packet.
+const CONNECT: u8 = 1;
+
+/// The MQTT control packet type of a PUBLISH packet.
+const PUBLISH: u8 = 3;
+
+/// The MQTT control packet type of a SUBSCRIBE packet.
+const SUBSCRIBE: u8 = 8;
+
+/// The MQTT control packet type of a PINGREQ packet.
+const PINGREQ: u8 = 12;
+
+/// The MQTT control packet type of a DISCONNECT packet.
+const DISCONNECT: u8 = 14;
+
+/// A message published to the fake MQTT broker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockMessage {
+    /// The topic
+    pub topic: String,
+    /// The payload
+    pub payload: String,
+    /// Whether the message is retained
+    pub retain: bool,
+}
+
+/// An action of the fake MQTT broker towards its connected clients.
+#[derive(Clone, Debug)]
+enum Outgoing {
+    /// Publish a message with the given topic and payload
+    Publish(String, String),
+    /// Close the connection
+    Disconnect,
+}
+
+/// The messages and subscriptions received by the fake MQTT broker.
+#[derive(Debug, Default)]
+struct Received {
+    /// The messages published so far, in the order they were received
+    messages: Vec<MockMessage>,
+    /// The topic filters subscribed to so far, in the order they were received
+    subscriptions: Vec<String>,
+}
+
+/// A running fake MQTT broker.
+#[derive(Debug)]
+pub struct MockMqtt {
+    /// The port the fake broker is served at (on localhost)
+    pub port: u16,
+    /// The messages and subscriptions received so far
+    received: Arc<Mutex<Received>>,
+    /// The sender of actions towards the connected clients
+    outgoing: broadcast::Sender<Outgoing>,
+}
+
+impl MockMqtt {
+    /// Starts a fake MQTT broker on a free local port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("free local port");
+        let port = listener
+            .local_addr()
+            .expect("listener has local address")
+            .port();
+        let received = Arc::new(Mutex::new(Received::default()));
+        let (outgoing, _) = broadcast::channel(16);
+        let (recorded, connections) = (Arc::clone(&received), outgoing.clone());
+        rocket::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let outgoing = connections.subscribe();
+                rocket::tokio::spawn(serve(stream, Arc::clone(&recorded), outgoing));
+            }
+        });
+
+        Self {
+            port,
+            received,
+            outgoing,
+        }
+    }
+
+    /// Returns the messages published so far.
+    pub fn messages(&self) -> Vec<MockMessage> {
+        self.received
+            .lock()
+            .expect("Mock received mutex was poisoned")
+            .messages
+            .clone()
+    }
+
+    /// Returns the topic filters subscribed to so far.
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.received
+            .lock()
+            .expect("Mock received mutex was poisoned")
+            .subscriptions
+            .clone()
+    }
+
+    /// Forgets the messages published so far, e.g. to simulate a restart without persistence.
+    pub fn clear_messages(&self) {
+        self.received
+            .lock()
+            .expect("Mock received mutex was poisoned")
+            .messages
+            .clear();
+    }
+
+    /// Publishes a message with the given topic and payload to all connected clients.
+    pub fn publish(&self, topic: &str, payload: &str) {
+        let message = Outgoing::Publish(topic.to_owned(), payload.to_owned());
+        self.outgoing.send(message).expect("clients are connected");
+    }
+
+    /// Closes the connections of all connected clients.
+    pub fn disconnect_clients(&self) {
+        self.outgoing
+            .send(Outgoing::Disconnect)
+            .expect("clients are connected");
+    }
+}
+
+/// Reads the remaining length of a packet, encoded as a variable byte integer.
+async fn read_remaining_length(stream: &mut TcpStream) -> io::Result<usize> {
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = stream.read_u8().await?;
+        length |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed remaining length",
+    ))
+}
+
+/// Returns the given remaining length of a packet, encoded as a variable byte integer.
+fn encode_remaining_length(mut length: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Serves the MQTT packets on the given connection until it is closed.
+async fn serve(
+    mut stream: TcpStream,
+    received: Arc<Mutex<Received>>,
+    mut outgoing: broadcast::Receiver<Outgoing>,
+) -> io::Result<()> {
+    loop {
+        let header = select! {
+            header = stream.read_u8() => match header {
+                Ok(header) => header,
+                Err(_) => return Ok(()),
+            },
+            Ok(action) = outgoing.recv() => {
+                match action {
+                    Outgoing::Publish(topic, payload) => {
+                        let length = 2 + topic.len() + payload.len();
+                        let mut packet = vec![0x30];
+                        packet.extend(encode_remaining_length(length));
+                        packet.extend((topic.len() as u16).to_be_bytes());
+                        packet.extend(topic.as_bytes());
+                        packet.extend(payload.as_bytes());
+                        stream.write_all(&packet).await?;
+                    }
+                    Outgoing::Disconnect => return Ok(()),
+                }
+                continue;
+            }
+        };
+        let length = read_remaining_length(&mut stream).await?;
+        let mut packet = vec![0; length];
+        stream.read_exact(&mut packet).await?;
+
+        match header >> 4 {
+            CONNECT => stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await?,
+            PUBLISH => {
+                let qos = (header >> 1) & 0x03;
+                let topic_length = usize::from(u16::from_be_bytes([packet[0], packet[1]]));
+                let topic = String::from_utf8_lossy(&packet[2..2 + topic_length]).into_owned();
+                let mut payload_start = 2 + topic_length;
+                if qos > 0 {
+                    let packet_id = &packet[payload_start..payload_start + 2];
+                    stream
+                        .write_all(&[0x40, 0x02, packet_id[0], packet_id[1]])
+                        .await?;
+                    payload_start += 2;
+                }
+                let payload = String::from_utf8_lossy(&packet[payload_start..]).into_owned();
+                let mut received = received.lock().expect("Mock received mutex was poisoned");
+                received.messages.push(MockMessage {
+                    topic,
+                    payload,
+                    retain: header & 0x01 != 0,
+                });
+            }
+            SUBSCRIBE => {
+                // Grant each requested topic filter with the requested QoS.
+                let mut granted = Vec::new();
+                let mut position = 2;
+                while position < packet.len() {
+                    let filter_length =
+                        usize::from(u16::from_be_bytes([packet[position], packet[position + 1]]));
+                    let filter_end = position + 2 + filter_length;
+                    let filter = String::from_utf8_lossy(&packet[position + 2..filter_end]);
+                    received
+                        .lock()
+                        .expect("Mock received mutex was poisoned")
+                        .subscriptions
+                        .push(filter.into_owned());
+                    granted.push(packet[filter_end]);
+                    position = filter_end + 1;
+                }
+                let mut suback = vec![0x90];
+                suback.extend(encode_remaining_length(2 + granted.len()));
+                suback.extend(&packet[..2]);
+                suback.extend(granted);
+                stream.write_all(&suback).await?;
+            }
+            PINGREQ => stream.write_all(&[0xd0, 0x00]).await?,
+            DISCONNECT => return Ok(()),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! A fake PVOutput.org API to test the scraper against.
+//!
+//! It implements the `addstatus` and `addbatchstatus` services and records the uploaded statuses.
+//! Failures of the services can be scripted by queueing HTTP statuses to respond with.
+
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{post, routes, FromForm, State};
+
+use super::mock_autarco::{free_port, wait_for_port};
+
+/// The API key that is accepted.
+pub const API_KEY: &str = "pvoutput-key";
+
+/// The system ID that is accepted.
+pub const SYSTEM_ID: &str = "12345";
+
+/// A status uploaded to the fake PVOutput API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockUpload {
+    /// The date (`yyyymmdd`)
+    pub date: String,
+    /// The time (`hh:mm`)
+    pub time: String,
+    /// The energy generation (Wh)
+    pub energy_wh: u64,
+    /// The power generation (W)
+    pub power_w: u32,
+}
+
+/// The state of the fake PVOutput API.
+#[derive(Debug, Default)]
+pub struct MockState {
+    /// The uploaded statuses, in the order they were received
+    pub uploads: Vec<MockUpload>,
+    /// The number of batches received
+    pub batches: usize,
+    /// The scripted HTTP statuses to respond with to the next requests
+    pub responses: VecDeque<Status>,
+}
+
+/// The shared state of the fake PVOutput API.
+type SharedState = Arc<Mutex<MockState>>;
+
+/// A running fake PVOutput API.
+#[derive(Debug)]
+pub struct MockPvOutput {
+    /// The base URL the fake API is served at
+    pub base_url: String,
+    /// The state of the fake API
+    pub state: SharedState,
+}
+
+impl MockPvOutput {
+    /// Starts a fake PVOutput API on a free local port.
+    pub async fn start() -> Self {
+        let port = free_port();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let figment = rocket::Config::figment()
+            .merge(("address", Ipv4Addr::LOCALHOST))
+            .merge(("port", port))
+            .merge(("log_level", "off"));
+        let rocket = rocket::custom(figment)
+            .mount("/service/r2", routes![add_status, add_batch_status])
+            .manage(Arc::clone(&state));
+        rocket::tokio::spawn(rocket.launch());
+        wait_for_port(port).await;
+
+        Self {
+            base_url: format!("http://{}:{}", Ipv4Addr::LOCALHOST, port),
+            state,
+        }
+    }
+
+    /// Queues scripted HTTP statuses to respond with to the next requests.
+    pub fn script(&self, responses: impl IntoIterator<Item = Status>) {
+        let mut state = self.state.lock().expect("Mock state mutex was poisoned");
+        state.responses.extend(responses);
+    }
+
+    /// Returns the statuses uploaded so far.
+    pub fn uploads(&self) -> Vec<MockUpload> {
+        self.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .uploads
+            .clone()
+    }
+}
+
+/// Request guard that checks the API key and system ID headers.
+#[derive(Debug)]
+struct Authorized;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        if headers.get_one("X-Pvoutput-Apikey") == Some(API_KEY)
+            && headers.get_one("X-Pvoutput-SystemId") == Some(SYSTEM_ID)
+        {
+            request::Outcome::Success(Authorized)
+        } else {
+            request::Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// The `addstatus` form.
+#[derive(Debug, FromForm)]
+struct AddStatus<'r> {
+    /// The date
+    d: &'r str,
+    /// The time
+    t: &'r str,
+    /// The energy generation
+    v1: u64,
+    /// The power generation
+    v2: u32,
+    /// The cumulative flag
+    c1: u8,
+}
+
+/// The `addbatchstatus` form.
+#[derive(Debug, FromForm)]
+struct AddBatchStatus<'r> {
+    /// The statuses, separated by semicolons
+    data: &'r str,
+    /// The cumulative flag
+    c1: u8,
+}
+
+/// Returns the next scripted HTTP status to respond with, if any.
+fn scripted_response(state: &mut MockState) -> Result<(), Status> {
+    match state.responses.pop_front() {
+        Some(status) => Err(status),
+        None => Ok(()),
+    }
+}
+
+/// Records the uploaded status.
+#[post("/addstatus.jsp", data = "<form>")]
+fn add_status(
+    _authorized: Authorized,
+    form: Form<AddStatus<'_>>,
+    state: &State<SharedState>,
+) -> Result<&'static str, Status> {
+    let mut state = state.lock().expect("Mock state mutex was poisoned");
+    scripted_response(&mut state)?;
+    if form.c1 != 2 {
+        return Err(Status::BadRequest);
+    }
+
+    state.uploads.push(MockUpload {
+        date: form.d.to_owned(),
+        time: form.t.to_owned(),
+        energy_wh: form.v1,
+        power_w: form.v2,
+    });
+
+    Ok("OK 200: Added Status")
+}
+
+/// Records the uploaded batch of statuses.
+#[post("/addbatchstatus.jsp", data = "<form>")]
+fn add_batch_status(
+    _authorized: Authorized,
+    form: Form<AddBatchStatus<'_>>,
+    state: &State<SharedState>,
+) -> Result<String, Status> {
+    let mut state = state.lock().expect("Mock state mutex was poisoned");
+    scripted_response(&mut state)?;
+    if form.c1 != 2 {
+        return Err(Status::BadRequest);
+    }
+
+    let mut results = Vec::new();
+    for entry in form.data.split(';') {
+        let fields = entry.split(',').collect::<Vec<_>>();
+        let upload = match fields.as_slice() {
+            [date, time, energy_wh, power_w] => MockUpload {
+                date: (*date).to_owned(),
+                time: (*time).to_owned(),
+                energy_wh: energy_wh.parse().map_err(|_| Status::BadRequest)?,
+                power_w: power_w.parse().map_err(|_| Status::BadRequest)?,
+            },
+            _ => return Err(Status::BadRequest),
+        };
+        results.push(format!("{},{},1", upload.date, upload.time));
+        state.uploads.push(upload);
+    }
+    state.batches += 1;
+
+    Ok(results.join(";"))
+}
@@ -0,0 +1,1370 @@
+//! Integration tests that run the scraper end to end against a fake My Autarco site (or a fake
+//! Modbus device).
+
+use std::collections::HashMap;
+use std::fs::Permissions;
+use std::net::Ipv4Addr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use reqwest::StatusCode;
+use rocket::http::Status;
+use rocket::tokio::time::{sleep, timeout};
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+use self::mock_autarco::{
+    free_port, wait_for_port, MockAccount, MockAutarco, MockResponse, MockState,
+};
+use self::mock_modbus::{MockModbus, MockRegisters};
+use self::mock_mqtt::MockMqtt;
+use self::mock_pvoutput::{MockPvOutput, MockUpload, API_KEY, SYSTEM_ID};
+
+// The code generated by Rocket for the routes and forms triggers lints outside the crate root,
+// and the scripted login responses of the fake site are only used by the client tests.
+#[allow(dead_code, unused_imports, renamed_and_removed_lints)]
+mod mock_autarco;
+mod mock_modbus;
+mod mock_mqtt;
+#[allow(unused_imports, renamed_and_removed_lints)]
+mod mock_pvoutput;
+
+/// The API authentication configuration with a token named `test` and secret `api-secret`.
+const AUTH_CONFIG: &str = concat!(
+    r#"{tokens=[{name="test","#,
+    r#"secret_sha256="014c243ff960e87afc8482648f41e2084dce765aa062dcdcbf4e0e43c4db8a41"}]}"#
+);
+
+/// The maximum time to wait for the scraper to provide a status.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A running scraper process.
+#[derive(Debug)]
+struct Scraper {
+    /// The scraper child process
+    child: Child,
+    /// The base URL the scraper API is served at
+    base_url: String,
+}
+
+impl Scraper {
+    /// Starts the scraper configured to use the given fake My Autarco site.
+    async fn start(mock: &MockAutarco) -> Self {
+        Self::start_with_env(mock, []).await
+    }
+
+    /// Starts the scraper configured to use the given fake My Autarco site and with additional
+    /// environment variables.
+    async fn start_with_env<'a>(
+        mock: &MockAutarco,
+        env: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        Self::launch(mock, true, env).await
+    }
+
+    /// Starts the scraper like [`Scraper::start_with_env`], but without the credentials of the
+    /// account, e.g. to only use providers that do not need them.
+    async fn start_without_credentials<'a>(
+        mock: &MockAutarco,
+        env: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        Self::launch(mock, false, env).await
+    }
+
+    /// Starts the scraper, configured with the credentials of the account if requested.
+    async fn launch<'a>(
+        mock: &MockAutarco,
+        with_credentials: bool,
+        env: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        let port = free_port();
+        let (username, password, site_id) = {
+            let state = mock.state.lock().expect("Mock state mutex was poisoned");
+            (
+                state.username.clone(),
+                state.password.clone(),
+                state.site_id.clone(),
+            )
+        };
+        let mut command = Command::new(env!("CARGO_BIN_EXE_autarco-scraper"));
+        command
+            .current_dir(env!("CARGO_TARGET_TMPDIR"))
+            .env("ROCKET_ADDRESS", Ipv4Addr::LOCALHOST.to_string())
+            .env("ROCKET_PORT", port.to_string())
+            .env("ROCKET_LOG_LEVEL", "off")
+            .env("ROCKET_BASE_URL", &mock.base_url)
+            .env("ROCKET_SITE_ID", site_id);
+        if with_credentials {
+            command
+                .env("ROCKET_USERNAME", username)
+                .env("ROCKET_PASSWORD", password);
+        }
+        let child = command
+            .envs(env)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("scraper can be started");
+        wait_for_port(port).await;
+
+        Self {
+            child,
+            base_url: format!("http://{}:{}", Ipv4Addr::LOCALHOST, port),
+        }
+    }
+
+    /// Waits until the response of the given API endpoint of the scraper satisfies the given
+    /// predicate and returns its HTTP status and (JSON) body.
+    ///
+    /// The body is `null` if it is not valid JSON.
+    async fn wait_until(
+        &self,
+        endpoint: &str,
+        predicate: impl Fn(StatusCode, &Value) -> bool,
+    ) -> (StatusCode, Value) {
+        let start = Instant::now();
+        loop {
+            let response = reqwest::get(format!("{}{}", self.base_url, endpoint))
+                .await
+                .expect("scraper is reachable");
+            let http_status = response.status();
+            let body = response.json().await.unwrap_or(Value::Null);
+            if predicate(http_status, &body) {
+                return (http_status, body);
+            }
+
+            assert!(start.elapsed() < TIMEOUT, "scraper did not respond in time");
+            sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Waits until the scraper provides a status and returns it.
+    async fn status(&self) -> Value {
+        let (_, status) = self
+            .wait_until("/", |http_status, _| http_status.is_success())
+            .await;
+
+        status
+    }
+}
+
+impl Drop for Scraper {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits until the file at the given path exists.
+async fn wait_for_file(path: &str) {
+    let start = Instant::now();
+    while !Path::new(path).exists() {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "scraper did not write the file in time"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Returns the PVOutput configuration to upload to the given fake PVOutput API, with the given
+/// additional settings.
+fn pvoutput_config(pvoutput: &MockPvOutput, settings: &str) -> String {
+    format!(
+        r#"{{base_url="{}",api_key="{}",system_id="{}",timezone="UTC"{}}}"#,
+        pvoutput.base_url, API_KEY, SYSTEM_ID, settings
+    )
+}
+
+/// Waits until the fake PVOutput API has received the given number of statuses and returns them.
+async fn wait_for_uploads(pvoutput: &MockPvOutput, count: usize) -> Vec<MockUpload> {
+    let start = Instant::now();
+    loop {
+        let uploads = pvoutput.uploads();
+        if uploads.len() >= count {
+            return uploads;
+        }
+
+        assert!(start.elapsed() < TIMEOUT, "scraper did not upload in time");
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Returns the PVOutput date and time (in UTC) of the status interval of the given timestamp.
+fn pvoutput_date_time(timestamp: u64) -> (String, String) {
+    let date_time = OffsetDateTime::from_unix_timestamp((timestamp - timestamp % 300) as i64)
+        .expect("valid timestamp");
+
+    (
+        format!(
+            "{:04}{:02}{:02}",
+            date_time.year(),
+            u8::from(date_time.month()),
+            date_time.day()
+        ),
+        format!("{:02}:{:02}", date_time.hour(), date_time.minute()),
+    )
+}
+
+/// Asserts that the status contains the KPI data served by the fake My Autarco site and was
+/// retrieved from the given source.
+fn assert_status(status: &Value, source: &str) {
+    let mut status = status.clone();
+    let fields = status.as_object_mut().expect("status is an object");
+    fields
+        .remove("last_updated")
+        .expect("status has a timestamp");
+    fields.remove("age_secs").expect("status has an age");
+
+    assert_eq!(
+        status,
+        json!({
+            "current_w": 23,
+            "today_kwh": 4,
+            "month_kwh": 112,
+            "total_kwh": 6159,
+            "source": source,
+            "stale": false
+        })
+    );
+}
+
+#[rocket::async_test]
+async fn updates_status() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start(&mock).await;
+
+    assert_status(&scraper.status().await, "my_autarco");
+    assert_eq!(mock.logins(), 1);
+}
+
+#[rocket::async_test]
+async fn updates_statuses_of_multiple_accounts() {
+    let mock = MockAutarco::start(MockState {
+        accounts: vec![MockAccount {
+            username: String::from("bar@domain.tld"),
+            password: String::from("another-secret"),
+            site_ids: vec![String::from("fgh456ij"), String::from("klm789no")],
+        }],
+        ..MockState::default()
+    })
+    .await;
+    let accounts = concat!(
+        r#"[{username="bar@domain.tld",password="another-secret","#,
+        r#"site_ids=["fgh456ij","klm789no"]}]"#
+    );
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_ACCOUNTS", accounts)]).await;
+
+    let (_, sites) = scraper
+        .wait_until("/sites", |_, sites| {
+            sites
+                .as_array()
+                .is_some_and(|sites| sites.iter().all(|site| site["status"].is_object()))
+        })
+        .await;
+    let site_ids = sites
+        .as_array()
+        .expect("sites is an array")
+        .iter()
+        .map(|site| site["site_id"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        site_ids,
+        [json!("abc123de"), json!("fgh456ij"), json!("klm789no")]
+    );
+    for site_id in ["abc123de", "fgh456ij", "klm789no"] {
+        let (_, status) = scraper
+            .wait_until(&format!("/sites/{}", site_id), |http_status, _| {
+                http_status.is_success()
+            })
+            .await;
+        assert_status(&status, "my_autarco");
+    }
+    let (http_status, _) = scraper.wait_until("/sites/unknown", |_, _| true).await;
+    assert_eq!(http_status, StatusCode::NOT_FOUND);
+
+    // Each account logs in once and keeps its own session, which only gives access to its sites.
+    let mut usernames = mock.logged_in_usernames();
+    usernames.sort();
+    assert_eq!(usernames, ["bar@domain.tld", "foo@domain.tld"]);
+}
+
+#[rocket::async_test]
+async fn updates_status_using_json_provider() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let provider = format!(
+        concat!(
+            r#"{{type="json",url="{}/json/{{site_id}}",current_w="/power/now","#,
+            r#"today_kwh="/energy/today",month_kwh="/energy/month",total_kwh="/energy/total"}}"#
+        ),
+        mock.base_url
+    );
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_PROVIDER", provider.as_str())]).await;
+
+    assert_status(&scraper.status().await, "json");
+    assert_eq!(mock.logins(), 0);
+    // The credentials of the account are not sent to the API.
+    assert_eq!(mock.json_authorization(), None);
+}
+
+#[rocket::async_test]
+async fn updates_status_using_json_provider_with_credentials() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let provider = format!(
+        concat!(
+            r#"{{type="json",url="{}/json/{{site_id}}",username="api-user",password="api-secret","#,
+            r#"current_w="/power/now",total_kwh="/energy/total"}}"#
+        ),
+        mock.base_url
+    );
+    let scraper =
+        Scraper::start_without_credentials(&mock, [("ROCKET_PROVIDER", provider.as_str())]).await;
+
+    let status = scraper.status().await;
+    assert_eq!(status["current_w"], json!(23));
+    assert_eq!(status["source"], json!("json"));
+    assert_eq!(
+        mock.json_authorization().as_deref(),
+        Some("Basic YXBpLXVzZXI6YXBpLXNlY3JldA==")
+    );
+}
+
+#[rocket::async_test]
+async fn updates_status_using_modbus_sunspec() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let modbus = MockModbus::start(MockRegisters::sunspec(23, 6_159_000)).await;
+    let provider = format!(r#"{{type="modbus",address="{}"}}"#, modbus.address);
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_PROVIDER", provider.as_str())]).await;
+
+    let status = scraper.status().await;
+    assert_eq!(status["current_w"], json!(23));
+    assert_eq!(status["today_kwh"], json!(null));
+    assert_eq!(status["month_kwh"], json!(null));
+    assert_eq!(status["total_kwh"], json!(6159));
+    assert_eq!(status["source"], json!("modbus"));
+    assert_eq!(mock.logins(), 0);
+}
+
+#[rocket::async_test]
+async fn updates_status_using_modbus_register_map() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let registers = MockRegisters {
+        input: HashMap::from([
+            (3004, 0),
+            (3005, 23),
+            (3008, 0),
+            (3009, 6159),
+            (3010, 0),
+            (3011, 112),
+            (3014, 40),
+        ]),
+        ..MockRegisters::default()
+    };
+    let modbus = MockModbus::start(registers).await;
+    let provider = format!(
+        concat!(
+            r#"{{type="modbus",address="{}",registers={{preset="custom","#,
+            r#"current_w={{address=3004,table="input",type="u32"}},"#,
+            r#"today_kwh={{address=3014,table="input",multiplier=0.1}},"#,
+            r#"month_kwh={{address=3010,table="input",type="u32"}},"#,
+            r#"total_kwh={{address=3008,table="input",type="u32"}}}}}}"#
+        ),
+        modbus.address
+    );
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_PROVIDER", provider.as_str())]).await;
+
+    assert_status(&scraper.status().await, "modbus");
+    assert_eq!(mock.logins(), 0);
+}
+
+#[rocket::async_test]
+async fn falls_back_to_next_provider() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::ServerError]);
+    let fallback_providers = format!(
+        concat!(
+            r#"[{{name="backup",type="json",url="{}/json/{{site_id}}",current_w="/power/now","#,
+            r#"today_kwh="/energy/today",month_kwh="/energy/month",total_kwh="/energy/total"}}]"#
+        ),
+        mock.base_url
+    );
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [("ROCKET_FALLBACK_PROVIDERS", fallback_providers.as_str())],
+    )
+    .await;
+
+    assert_status(&scraper.status().await, "backup");
+    assert_eq!(mock.logins(), 1);
+}
+
+/// Starts the scraper with a wrong password for the My Autarco site, falling back to the JSON
+/// provider and polling every second using the given backoff configuration.
+async fn start_with_rejected_primary(mock: &MockAutarco, backoff: &str) -> Scraper {
+    let fallback_providers = format!(
+        concat!(
+            r#"[{{type="json",url="{}/json/{{site_id}}",current_w="/power/now","#,
+            r#"today_kwh="/energy/today",month_kwh="/energy/month",total_kwh="/energy/total"}}]"#
+        ),
+        mock.base_url
+    );
+    Scraper::start_with_env(
+        mock,
+        [
+            ("ROCKET_PASSWORD", "wrong"),
+            ("ROCKET_FALLBACK_PROVIDERS", fallback_providers.as_str()),
+            ("ROCKET_POLL_INTERVAL", "1"),
+            ("ROCKET_WAKE_INTERVAL", "1"),
+            ("ROCKET_BACKOFF", backoff),
+        ],
+    )
+    .await
+}
+
+/// Waits until the scraper has updated the status using the JSON provider a few more times.
+async fn wait_for_json_updates(scraper: &Scraper) {
+    let status = scraper.status().await;
+    assert_status(&status, "json");
+    let first_updated = status["last_updated"]
+        .as_u64()
+        .expect("status has a timestamp");
+    scraper
+        .wait_until("/", |_, status| {
+            status["last_updated"]
+                .as_u64()
+                .is_some_and(|last_updated| last_updated >= first_updated + 3)
+        })
+        .await;
+}
+
+#[rocket::async_test]
+async fn backs_off_from_provider_after_rejected_login() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = start_with_rejected_primary(&mock, "{initial_secs=3600}").await;
+
+    wait_for_json_updates(&scraper).await;
+    assert_eq!(mock.logins(), 1);
+}
+
+#[rocket::async_test]
+async fn skips_provider_with_open_circuit_breaker() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let backoff = "{initial_secs=1,max_secs=1,auth_failure_threshold=2,circuit_open_secs=3600}";
+    let scraper = start_with_rejected_primary(&mock, backoff).await;
+
+    let start = Instant::now();
+    while mock.logins() < 2 {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "scraper did not log in again in time"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+    wait_for_json_updates(&scraper).await;
+    assert_eq!(mock.logins(), 2);
+}
+
+#[rocket::async_test]
+async fn reports_disagreeing_providers() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let modbus = MockModbus::start(MockRegisters::sunspec(523, 6_159_000)).await;
+    let fallback_providers = format!(
+        r#"[{{name="inverter",type="modbus",address="{}"}}]"#,
+        modbus.address
+    );
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_FALLBACK_PROVIDERS", fallback_providers.as_str()),
+            ("ROCKET_CROSS_CHECK", "{max_power_difference_w=100}"),
+        ],
+    )
+    .await;
+    assert_status(&scraper.status().await, "my_autarco");
+
+    let (_, errors) = scraper
+        .wait_until("/errors", |_, errors| {
+            errors.as_array().is_some_and(|errors| !errors.is_empty())
+        })
+        .await;
+    assert_eq!(errors[0]["kind"], json!("disagreement"));
+    assert_eq!(
+        errors[0]["message"],
+        json!("inverter disagrees with my_autarco: current_w is 23 vs. 523")
+    );
+}
+
+#[rocket::async_test]
+async fn logs_in_again_when_unauthorized() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::Unauthorized]);
+    let scraper = Scraper::start(&mock).await;
+
+    assert_status(&scraper.status().await, "my_autarco");
+    assert_eq!(mock.logins(), 2);
+}
+
+#[rocket::async_test]
+async fn recovers_from_server_errors() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::ServerError]);
+    let scraper = Scraper::start(&mock).await;
+
+    assert_status(&scraper.status().await, "my_autarco");
+    assert_eq!(mock.logins(), 1);
+}
+
+#[rocket::async_test]
+async fn recovers_from_malformed_responses() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::Malformed]);
+    let scraper = Scraper::start(&mock).await;
+
+    assert_status(&scraper.status().await, "my_autarco");
+    assert_eq!(mock.logins(), 1);
+}
+
+#[rocket::async_test]
+async fn publishes_to_mqtt() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let mqtt = MockMqtt::start().await;
+    let mqtt_config = format!(r#"{{host="{}",port={}}}"#, Ipv4Addr::LOCALHOST, mqtt.port);
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_MQTT", mqtt_config.as_str())]).await;
+    scraper.status().await;
+
+    let start = Instant::now();
+    let messages = loop {
+        let messages = mqtt.messages();
+        if messages
+            .iter()
+            .any(|message| message.topic == "autarco/abc123de/state")
+        {
+            break messages;
+        }
+
+        assert!(start.elapsed() < TIMEOUT, "scraper did not publish in time");
+        sleep(Duration::from_millis(500)).await;
+    };
+    let discovery = messages
+        .iter()
+        .filter(|message| {
+            message
+                .topic
+                .starts_with("homeassistant/sensor/autarco_abc123de/")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(discovery.len(), 4);
+    assert!(discovery.iter().all(|message| message.retain));
+    let config: Value = serde_json::from_str(&discovery[1].payload).expect("valid JSON");
+    assert_eq!(config["state_topic"], json!("autarco/abc123de/state"));
+    assert_eq!(config["device_class"], json!("energy"));
+    assert_eq!(config["state_class"], json!("total_increasing"));
+
+    let state = messages
+        .iter()
+        .find(|message| message.topic == "autarco/abc123de/state")
+        .expect("status is published");
+    assert!(!state.retain);
+    let status: Value = serde_json::from_str(&state.payload).expect("valid JSON");
+    assert_eq!(status["current_w"], json!(23));
+    assert_eq!(status["total_kwh"], json!(6159));
+}
+
+/// Waits until the given fake MQTT broker has received the discovery configurations of all
+/// sensors of the site.
+async fn wait_for_discovery(mqtt: &MockMqtt) {
+    let start = Instant::now();
+    loop {
+        let configs = mqtt
+            .messages()
+            .into_iter()
+            .filter(|message| message.topic.ends_with("/config") && message.retain)
+            .count();
+        if configs >= 4 {
+            return;
+        }
+
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "scraper did not announce in time"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[rocket::async_test]
+async fn republishes_mqtt_discovery() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let mqtt = MockMqtt::start().await;
+    let mqtt_config = format!(r#"{{host="{}",port={}}}"#, Ipv4Addr::LOCALHOST, mqtt.port);
+    let _scraper = Scraper::start_with_env(&mock, [("ROCKET_MQTT", mqtt_config.as_str())]).await;
+    wait_for_discovery(&mqtt).await;
+    let start = Instant::now();
+    while !mqtt
+        .subscriptions()
+        .contains(&String::from("homeassistant/status"))
+    {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "scraper did not subscribe in time"
+        );
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    // Home Assistant comes online again.
+    mqtt.clear_messages();
+    mqtt.publish("homeassistant/status", "online");
+    wait_for_discovery(&mqtt).await;
+
+    // The broker restarts and loses the retained messages.
+    mqtt.clear_messages();
+    mqtt.disconnect_clients();
+    wait_for_discovery(&mqtt).await;
+}
+
+#[rocket::async_test]
+async fn keeps_updating_without_mqtt_broker() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    // Nothing listens on the port, so the messages can never be delivered.
+    let mqtt_config = format!(r#"{{host="{}",port={}}}"#, Ipv4Addr::LOCALHOST, free_port());
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_MQTT", mqtt_config.as_str()),
+            ("ROCKET_POLL_INTERVAL", "1"),
+            ("ROCKET_WAKE_INTERVAL", "1"),
+        ],
+    )
+    .await;
+    let first_updated = scraper.status().await["last_updated"]
+        .as_u64()
+        .expect("status has a timestamp");
+
+    // More statuses than fit in the outgoing MQTT queue are stored in the meantime.
+    scraper
+        .wait_until("/", |_, status| {
+            status["last_updated"]
+                .as_u64()
+                .is_some_and(|last_updated| last_updated >= first_updated + 15)
+        })
+        .await;
+}
+
+#[rocket::async_test]
+async fn reports_invalid_credentials() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_PASSWORD", "wrong")]).await;
+
+    let (http_status, health) = scraper
+        .wait_until("/ready", |_, health| {
+            !health["updaters"][0]["login_failure"].is_null()
+        })
+        .await;
+    assert_eq!(http_status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["healthy"], json!(false));
+    assert_eq!(health["updaters"][0]["logged_in"], json!(false));
+    assert_eq!(
+        health["updaters"][0]["login_failure"]["kind"],
+        json!("invalid_credentials")
+    );
+}
+
+#[rocket::async_test]
+async fn backs_off_after_failed_login() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_PASSWORD", "wrong"),
+            ("ROCKET_BACKOFF", "{initial_secs=3600}"),
+        ],
+    )
+    .await;
+
+    let (_, health) = scraper
+        .wait_until("/ready", |_, health| {
+            health["updaters"][0]["supervisor"]["phase"] == json!("backing_off")
+        })
+        .await;
+    let supervisor = &health["updaters"][0]["supervisor"];
+    assert_eq!(supervisor["restarts"], json!(1));
+    assert_eq!(supervisor["auth_failures"], json!(1));
+    assert!(supervisor["retry_at"].is_u64());
+
+    // No login is attempted again while backing off.
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(mock.logins(), 1);
+}
+
+#[rocket::async_test]
+async fn opens_circuit_breaker_after_rejected_logins() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_PASSWORD", "wrong"),
+            (
+                "ROCKET_BACKOFF",
+                "{initial_secs=1,max_secs=1,auth_failure_threshold=3,circuit_open_secs=3600}",
+            ),
+        ],
+    )
+    .await;
+
+    let (_, health) = scraper
+        .wait_until("/ready", |_, health| {
+            health["updaters"][0]["supervisor"]["phase"] == json!("circuit_open")
+        })
+        .await;
+    assert_eq!(
+        health["updaters"][0]["supervisor"]["auth_failures"],
+        json!(3)
+    );
+    assert_eq!(mock.logins(), 3);
+
+    // No login is attempted anymore while the circuit breaker is open.
+    sleep(Duration::from_secs(3)).await;
+    assert_eq!(mock.logins(), 3);
+}
+
+#[rocket::async_test]
+async fn restores_session_from_cookie_jar() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let cookie_jar_dir = format!("{}/cookies-{}", env!("CARGO_TARGET_TMPDIR"), free_port());
+    std::fs::create_dir(&cookie_jar_dir).expect("cookie jar directory can be created");
+    let cookie_jar_path = format!("{}/foo_domain_tld.cookies.json", cookie_jar_dir);
+    let env = [("ROCKET_COOKIE_JAR_DIR", cookie_jar_dir.as_str())];
+    let scraper = Scraper::start_with_env(&mock, env).await;
+    assert_status(&scraper.status().await, "my_autarco");
+    wait_for_file(&cookie_jar_path).await;
+    drop(scraper);
+    assert_eq!(mock.logins(), 1);
+
+    // Leave a temporary file behind that is readable by others.
+    let tmp_path = format!("{}.tmp", cookie_jar_path);
+    std::fs::write(&tmp_path, "").expect("temporary file can be written");
+    std::fs::set_permissions(&tmp_path, Permissions::from_mode(0o644))
+        .expect("permissions can be set");
+
+    // The session is restored after a restart, so it does not log in again.
+    let scraper = Scraper::start_with_env(&mock, env).await;
+    assert_status(&scraper.status().await, "my_autarco");
+    assert_eq!(mock.logins(), 1);
+    assert!(!Path::new(&tmp_path).exists());
+    let metadata = std::fs::metadata(&cookie_jar_path).expect("cookie jar exists");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+}
+
+#[rocket::async_test]
+async fn restores_statuses_as_stale() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let state_path = format!("{}/state-{}.json", env!("CARGO_TARGET_TMPDIR"), free_port());
+    let scraper =
+        Scraper::start_with_env(&mock, [("ROCKET_STATE_PATH", state_path.as_str())]).await;
+    let status = scraper.status().await;
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&scraper.base_url)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    let etag = response
+        .headers()
+        .get("ETag")
+        .expect("response has an ETag")
+        .clone();
+    wait_for_file(&state_path).await;
+    drop(scraper);
+
+    // Logging in fails after the restart, so the restored status is not updated.
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_STATE_PATH", state_path.as_str()),
+            ("ROCKET_PASSWORD", "wrong"),
+        ],
+    )
+    .await;
+    let restored = scraper.status().await;
+    assert_eq!(restored["stale"], json!(true));
+    assert_eq!(restored["last_updated"], status["last_updated"]);
+    assert_eq!(restored["current_w"], json!(23));
+    assert_eq!(restored["total_kwh"], json!(6159));
+
+    // The entity tag of the status changes when it becomes stale.
+    let response = client
+        .get(&scraper.base_url)
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[rocket::async_test]
+async fn serves_status_history() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let history_path = format!(
+        "{}/history-{}.sqlite",
+        env!("CARGO_TARGET_TMPDIR"),
+        free_port()
+    );
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_HISTORY_PATH", history_path.as_str()),
+            ("ROCKET_POLL_INTERVAL", "1"),
+            ("ROCKET_WAKE_INTERVAL", "1"),
+        ],
+    )
+    .await;
+
+    // Wait until the status has been polled at least twice.
+    let (_, history) = scraper
+        .wait_until("/history", |_, history| {
+            history.as_array().is_some_and(|history| history.len() >= 2)
+        })
+        .await;
+    let first = &history[0];
+    let second = &history[1];
+    assert_eq!(first["current_w"], json!(23));
+    assert_eq!(first["total_kwh"], json!(6159));
+    assert_eq!(first["source"], json!("my_autarco"));
+    let first_updated = first["last_updated"]
+        .as_u64()
+        .expect("sample has a timestamp");
+    let second_updated = second["last_updated"]
+        .as_u64()
+        .expect("sample has a timestamp");
+    assert!(first_updated < second_updated);
+
+    // The range is inclusive and filters on both ends.
+    let (_, history) = scraper
+        .wait_until(
+            &format!("/history?from={}&to={}", first_updated, first_updated),
+            |http_status, _| http_status.is_success(),
+        )
+        .await;
+    assert_eq!(history, json!([first]));
+    let (_, history) = scraper
+        .wait_until(
+            &format!("/history?from={}&to={}", second_updated, second_updated),
+            |http_status, _| http_status.is_success(),
+        )
+        .await;
+    assert_eq!(history, json!([second]));
+    let (_, history) = scraper
+        .wait_until(
+            &format!("/history?from={}", first_updated + 1),
+            |http_status, _| http_status.is_success(),
+        )
+        .await;
+    assert_eq!(&history[0], second);
+    let (_, history) = scraper
+        .wait_until(
+            &format!("/history?to={}", first_updated - 1),
+            |http_status, _| http_status.is_success(),
+        )
+        .await;
+    assert_eq!(history, json!([]));
+}
+
+#[rocket::async_test]
+async fn responds_not_modified() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start(&mock).await;
+    scraper.status().await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&scraper.base_url)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    let etag = response
+        .headers()
+        .get("ETag")
+        .expect("response has an ETag")
+        .clone();
+    let last_modified = response
+        .headers()
+        .get("Last-Modified")
+        .expect("response has a Last-Modified header")
+        .clone();
+
+    let response = client
+        .get(&scraper.base_url)
+        .header("If-None-Match", etag)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = client
+        .get(&scraper.base_url)
+        .header("If-Modified-Since", last_modified)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[rocket::async_test]
+async fn forbids_refresh_without_authentication() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_REFRESH_MIN_INTERVAL", "0")]).await;
+    assert_status(&scraper.status().await, "my_autarco");
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("{}/refresh", scraper.base_url))
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[rocket::async_test]
+async fn requires_authentication() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_AUTH", AUTH_CONFIG),
+            ("ROCKET_REFRESH_MIN_INTERVAL", "0"),
+        ],
+    )
+    .await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&scraper.base_url)
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key("WWW-Authenticate"));
+
+    let response = client
+        .get(&scraper.base_url)
+        .bearer_auth("wrong-secret")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let (http_status, _) = scraper.wait_until("/health", |_, _| true).await;
+    assert_ne!(http_status, StatusCode::UNAUTHORIZED);
+
+    let start = Instant::now();
+    let status = loop {
+        let response = client
+            .get(&scraper.base_url)
+            .basic_auth("test", Some("api-secret"))
+            .send()
+            .await
+            .expect("scraper is reachable");
+        if response.status().is_success() {
+            break response
+                .json::<Value>()
+                .await
+                .expect("status is valid JSON");
+        }
+
+        assert!(start.elapsed() < TIMEOUT, "scraper did not respond in time");
+        sleep(Duration::from_millis(500)).await;
+    };
+    assert_status(&status, "my_autarco");
+
+    mock.state
+        .lock()
+        .expect("Mock state mutex was poisoned")
+        .pv_now = 42;
+    let response = client
+        .post(format!("{}/refresh", scraper.base_url))
+        .bearer_auth("api-secret")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::OK);
+    let status: Value = response.json().await.expect("status is valid JSON");
+    assert_eq!(status["current_w"], json!(42));
+}
+
+#[rocket::async_test]
+async fn coalesces_refresh_requests() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_AUTH", AUTH_CONFIG),
+            ("ROCKET_REFRESH_MIN_INTERVAL", "5"),
+        ],
+    )
+    .await;
+    scraper
+        .wait_until("/ready", |http_status, _| http_status.is_success())
+        .await;
+    // Make sure that the minimum refresh interval has passed since the first poll.
+    sleep(Duration::from_secs(5)).await;
+    let kpi_requests = mock.kpi_requests();
+
+    mock.state
+        .lock()
+        .expect("Mock state mutex was poisoned")
+        .pv_now = 42;
+    let refresh_url = format!("{}/refresh", scraper.base_url);
+    let requests = (0..5)
+        .map(|_| {
+            let refresh_url = refresh_url.clone();
+            rocket::tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(refresh_url)
+                    .bearer_auth("api-secret")
+                    .send()
+                    .await
+                    .expect("scraper is reachable")
+            })
+        })
+        .collect::<Vec<_>>();
+    for request in requests {
+        let response = request.await.expect("request task completes");
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: Value = response.json().await.expect("status is valid JSON");
+        assert_eq!(status["current_w"], json!(42));
+    }
+
+    // A single poll retrieves both the energy and the power KPI data.
+    assert_eq!(mock.kpi_requests(), kpi_requests + 2);
+}
+
+#[rocket::async_test]
+async fn limits_refreshes_after_failed_poll() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    mock.script([MockResponse::ServerError]);
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_AUTH", AUTH_CONFIG),
+            ("ROCKET_REFRESH_MIN_INTERVAL", "3600"),
+            ("ROCKET_BACKOFF", "{initial_secs=3600}"),
+        ],
+    )
+    .await;
+    let start = Instant::now();
+    while mock.kpi_requests() == 0 {
+        assert!(start.elapsed() < TIMEOUT, "scraper did not poll in time");
+        sleep(Duration::from_millis(100)).await;
+    }
+    // Wait until the failed poll has been handled by the update loop.
+    sleep(Duration::from_secs(1)).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/refresh", scraper.base_url))
+        .bearer_auth("api-secret")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after = response
+        .headers()
+        .get("Retry-After")
+        .and_then(|retry_after| retry_after.to_str().ok())
+        .and_then(|retry_after| retry_after.parse::<u64>().ok())
+        .expect("response has a Retry-After header");
+    assert!((3500..=3600).contains(&retry_after));
+    assert_eq!(mock.kpi_requests(), 1);
+}
+
+/// Reads the server-sent events of the given response until a `status` event with the given
+/// current power production arrives.
+async fn wait_for_status_event(response: &mut reqwest::Response, current_w: u32) {
+    let start = Instant::now();
+    let mut buffer = String::new();
+    loop {
+        while let Some(end) = buffer.find("\n\n") {
+            let event = buffer.drain(..end + 2).collect::<String>();
+            let field = |name: &str| {
+                event
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(str::trim)
+            };
+            if field("event:") != Some("status") {
+                continue;
+            }
+            let status: Value = serde_json::from_str(field("data:").expect("event has data"))
+                .expect("status is valid JSON");
+            if status["current_w"] == json!(current_w) {
+                return;
+            }
+        }
+
+        let remaining = TIMEOUT.saturating_sub(start.elapsed());
+        let chunk = timeout(remaining, response.chunk())
+            .await
+            .expect("scraper did not send the event in time")
+            .expect("scraper is reachable")
+            .expect("event stream is open");
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+    }
+}
+
+#[rocket::async_test]
+async fn streams_status_events() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_AUTH", AUTH_CONFIG),
+            ("ROCKET_REFRESH_MIN_INTERVAL", "0"),
+        ],
+    )
+    .await;
+    scraper
+        .wait_until("/ready", |http_status, _| http_status.is_success())
+        .await;
+
+    let client = reqwest::Client::new();
+    let mut current_w = 23;
+    for endpoint in ["/events", "/sites/abc123de/events"] {
+        let mut response = client
+            .get(format!("{}{}", scraper.base_url, endpoint))
+            .bearer_auth("api-secret")
+            .send()
+            .await
+            .expect("scraper is reachable");
+        assert_eq!(response.status(), StatusCode::OK);
+        // The last known status is sent immediately after subscribing.
+        wait_for_status_event(&mut response, current_w).await;
+
+        current_w += 1;
+        mock.state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .pv_now = current_w;
+        let refresh = client
+            .post(format!("{}/refresh", scraper.base_url))
+            .bearer_auth("api-secret")
+            .send()
+            .await
+            .expect("scraper is reachable");
+        assert_eq!(refresh.status(), StatusCode::OK);
+        wait_for_status_event(&mut response, current_w).await;
+    }
+}
+
+#[rocket::async_test]
+async fn supports_cors() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start_with_env(
+        &mock,
+        [(
+            "ROCKET_CORS",
+            r#"{allowed_origins=["http://dashboard.test"]}"#,
+        )],
+    )
+    .await;
+    scraper.status().await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .request(reqwest::Method::OPTIONS, &scraper.base_url)
+        .header("Origin", "http://dashboard.test")
+        .header("Access-Control-Request-Method", "GET")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let headers = response.headers();
+    assert_eq!(
+        headers["Access-Control-Allow-Origin"],
+        "http://dashboard.test"
+    );
+    assert_eq!(headers["Access-Control-Allow-Methods"], "GET, POST");
+    assert_eq!(headers["Access-Control-Max-Age"], "86400");
+
+    let response = client
+        .get(&scraper.base_url)
+        .header("Origin", "http://dashboard.test")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert_eq!(
+        response.headers()["Access-Control-Allow-Origin"],
+        "http://dashboard.test"
+    );
+
+    let response = client
+        .get(&scraper.base_url)
+        .header("Origin", "http://elsewhere.test")
+        .send()
+        .await
+        .expect("scraper is reachable");
+    assert!(!response
+        .headers()
+        .contains_key("Access-Control-Allow-Origin"));
+}
+
+#[rocket::async_test]
+async fn serves_versioned_api() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start(&mock).await;
+    scraper.status().await;
+
+    let (http_status, status) = scraper
+        .wait_until("/api/v1/status", |http_status, _| http_status.is_success())
+        .await;
+    assert_eq!(http_status, StatusCode::OK);
+    assert_status(&status, "my_autarco");
+
+    let (http_status, document) = scraper
+        .wait_until("/api/v1/openapi.json", |_, _| true)
+        .await;
+    assert_eq!(http_status, StatusCode::OK);
+    assert_eq!(document["openapi"], json!("3.0.3"));
+    assert!(document["components"]["schemas"]["Status"].is_object());
+
+    // All mounted routes are documented and only those.
+    let paths = document["paths"].as_object().expect("document has paths");
+    let mut operations = paths
+        .iter()
+        .flat_map(|(path, item)| {
+            let item = item.as_object().expect("path item is an object");
+            item.iter().map(move |(method, operation)| {
+                assert!(
+                    operation["summary"].is_string(),
+                    "{method} {path} is not described"
+                );
+                format!("{} {path}", method.to_uppercase())
+            })
+        })
+        .collect::<Vec<_>>();
+    operations.sort();
+    assert_eq!(
+        operations,
+        vec![
+            "GET /docs",
+            "GET /errors",
+            "GET /events",
+            "GET /health",
+            "GET /history",
+            "GET /metrics",
+            "GET /openapi.json",
+            "GET /ready",
+            "GET /sites",
+            "GET /sites/{site_id}",
+            "GET /sites/{site_id}/events",
+            "GET /sites/{site_id}/history",
+            "GET /status",
+            "POST /refresh",
+            "POST /sites/{site_id}/refresh",
+        ]
+    );
+    let responses = &document["paths"]["/status"]["get"]["responses"];
+    assert!(responses["401"].is_object());
+    let responses = &document["paths"]["/health"]["get"]["responses"];
+    assert!(responses["401"].is_null());
+}
+
+#[rocket::async_test]
+async fn serves_metrics() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let scraper = Scraper::start(&mock).await;
+    scraper.status().await;
+
+    let metrics = reqwest::get(format!("{}/metrics", scraper.base_url))
+        .await
+        .expect("scraper is reachable")
+        .text()
+        .await
+        .expect("metrics are text");
+    let lines = metrics.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&r#"autarco_current_w{site_id="abc123de"} 23"#));
+    assert!(lines.contains(&r#"autarco_total_kwh{site_id="abc123de"} 6159"#));
+    assert!(lines.contains(&"autarco_polls_total 1"));
+    assert!(lines.contains(&"autarco_logins_total 1"));
+    for endpoint in ["login", "energy", "power"] {
+        let count = format!(
+            r#"autarco_upstream_request_duration_seconds_count{{endpoint="{}"}} 1"#,
+            endpoint
+        );
+        assert!(lines.contains(&count.as_str()), "{} is missing", count);
+    }
+    assert!(lines.iter().any(|line| line
+        .starts_with(r#"autarco_upstream_request_duration_seconds_bucket{endpoint="power",le="#)));
+}
+
+#[rocket::async_test]
+async fn uploads_to_pvoutput() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let pvoutput = MockPvOutput::start().await;
+    let config = pvoutput_config(&pvoutput, "");
+    let scraper = Scraper::start_with_env(&mock, [("ROCKET_PVOUTPUT", config.as_str())]).await;
+    let status = scraper.status().await;
+
+    let uploads = wait_for_uploads(&pvoutput, 1).await;
+    let last_updated = status["last_updated"]
+        .as_u64()
+        .expect("status has a timestamp");
+    let (date, time) = pvoutput_date_time(last_updated);
+    assert_eq!(
+        uploads,
+        vec![MockUpload {
+            date,
+            time,
+            energy_wh: 6_159_000,
+            power_w: 23
+        }]
+    );
+}
+
+#[rocket::async_test]
+async fn backfills_pvoutput_after_outage() {
+    let mock = MockAutarco::start(MockState::default()).await;
+    let pvoutput = MockPvOutput::start().await;
+    pvoutput.script([Status::ServiceUnavailable]);
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("valid system time")
+        .as_secs();
+    let interval_start = timestamp - timestamp % 300;
+    let queue_path = format!(
+        "{}/pvoutput-queue-{}.json",
+        env!("CARGO_TARGET_TMPDIR"),
+        free_port()
+    );
+    let queue = json!([
+        {"timestamp": interval_start - 600, "energy_wh": 6_158_000, "power_w": 12},
+        {"timestamp": interval_start - 300, "energy_wh": 6_158_000, "power_w": 17}
+    ]);
+    std::fs::write(&queue_path, queue.to_string()).expect("queue file can be written");
+    let config = pvoutput_config(&pvoutput, &format!(r#",queue_path="{}""#, queue_path));
+    let _scraper = Scraper::start_with_env(
+        &mock,
+        [
+            ("ROCKET_PVOUTPUT", config.as_str()),
+            ("ROCKET_BACKOFF", "{initial_secs=1}"),
+        ],
+    )
+    .await;
+
+    let uploads = wait_for_uploads(&pvoutput, 3).await;
+    let powers = uploads
+        .iter()
+        .map(|upload| upload.power_w)
+        .collect::<Vec<_>>();
+    assert_eq!(powers, vec![12, 17, 23]);
+    assert_eq!(
+        pvoutput
+            .state
+            .lock()
+            .expect("Mock state mutex was poisoned")
+            .batches,
+        1
+    );
+    let (date, time) = pvoutput_date_time(interval_start - 600);
+    assert_eq!(uploads[0].date, date);
+    assert_eq!(uploads[0].time, time);
+}